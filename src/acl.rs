@@ -0,0 +1,162 @@
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::commands::{self, Category};
+use crate::resp::RedisValue;
+
+pub const DEFAULT_USER: &str = "default";
+
+#[derive(Clone)]
+pub struct User {
+    enabled: bool,
+    // `nopass`: any password is accepted
+    nopass: bool,
+    passwords: HashSet<String>,
+    categories: HashSet<Category>,
+}
+
+impl User {
+    /// A user as created by ACL SETUSER: disabled, with no way to
+    /// authenticate and no commands.
+    fn new() -> Self {
+        User {
+            enabled: false,
+            nopass: false,
+            passwords: HashSet::new(),
+            categories: HashSet::new(),
+        }
+    }
+
+    fn apply_rule(&mut self, rule: &str) -> Result<()> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allcommands" | "+@all" => self.categories.extend(Category::ALL),
+            "nocommands" | "-@all" => self.categories.clear(),
+            _ => {
+                if let Some(password) = rule.strip_prefix('>') {
+                    self.nopass = false;
+                    self.passwords.insert(password.to_owned());
+                } else if let Some(password) = rule.strip_prefix('<') {
+                    self.passwords.remove(password);
+                } else if let Some(category) = rule.strip_prefix("+@") {
+                    self.categories.insert(parse_category(rule, category)?);
+                } else if let Some(category) = rule.strip_prefix("-@") {
+                    self.categories.remove(&parse_category(rule, category)?);
+                } else {
+                    return Err(anyhow::anyhow!(
+                        "Error in ACL SETUSER modifier '{}': Syntax error",
+                        rule
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_category(rule: &str, category: &str) -> Result<Category> {
+    Category::from_name(category).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Error in ACL SETUSER modifier '{}': Unknown command or category name in ACL",
+            rule
+        )
+    })
+}
+
+lazy_static::lazy_static! {
+    static ref USERS: Mutex<HashMap<String, User>> = Mutex::new(HashMap::new());
+}
+
+/// Seeds the default user with every command. `--requirepass` becomes its
+/// password; without it the user is `nopass`.
+pub fn init(requirepass: Option<String>) {
+    let mut user = User::new();
+    user.enabled = true;
+    user.categories.extend(Category::ALL);
+    match requirepass {
+        Some(password) => {
+            user.passwords.insert(password);
+        }
+        None => user.nopass = true,
+    }
+    USERS.lock().unwrap().insert(DEFAULT_USER.to_owned(), user);
+}
+
+/// New connections start out authenticated as the default user unless it
+/// needs a password.
+pub fn default_user_requires_auth() -> bool {
+    match USERS.lock().unwrap().get(DEFAULT_USER) {
+        Some(user) => !(user.enabled && user.nopass),
+        None => false,
+    }
+}
+
+pub fn authenticate(username: &str, password: &str) -> bool {
+    match USERS.lock().unwrap().get(username) {
+        Some(user) => user.enabled && (user.nopass || user.passwords.contains(password)),
+        None => false,
+    }
+}
+
+/// Creates the user if needed, then applies each rule in order. No rule is
+/// applied unless all of them are valid.
+pub fn set_user(username: &str, rules: &[String]) -> Result<()> {
+    let mut users = USERS.lock().unwrap();
+    let mut user = users.get(username).cloned().unwrap_or_else(User::new);
+    for rule in rules {
+        user.apply_rule(rule)?;
+    }
+    users.insert(username.to_owned(), user);
+    Ok(())
+}
+
+/// The command as it may be logged or shown to MONITOR clients: the password
+/// rules of ACL SETUSER (`>pass`, `<pass`, `#hash`, `!hash`) are replaced
+/// with `(redacted)`, as Redis does.
+pub fn redact(command: &RedisValue) -> RedisValue {
+    let RedisValue::Array(items) = command else {
+        return command.clone();
+    };
+    let is_setuser = matches!(
+        (items.first(), items.get(1)),
+        (Some(RedisValue::BulkString(name)), Some(RedisValue::BulkString(sub)))
+            if name.eq_ignore_ascii_case("acl") && sub.eq_ignore_ascii_case("setuser")
+    );
+    if !is_setuser {
+        return command.clone();
+    }
+    let items = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| match item {
+            // the username comes before the rules
+            RedisValue::BulkString(rule) if i > 2 && rule.starts_with(['>', '<', '#', '!']) => {
+                RedisValue::BulkString("(redacted)".to_owned())
+            }
+            _ => item.clone(),
+        })
+        .collect();
+    RedisValue::Array(items)
+}
+
+/// Whether `username` may run `command`. Unknown commands are let through so
+/// they fail with the usual unknown command error.
+pub fn can_run(username: &str, command: &str) -> bool {
+    let Some(spec) = commands::lookup(command) else {
+        return true;
+    };
+    match USERS.lock().unwrap().get(username) {
+        Some(user) => user.categories.contains(&spec.category),
+        None => false,
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::resp::{parse_message, Incomplete, Protocol, RedisValue};
+
+/// How often the append-only file is fsync'd to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AppendFsync {
+    /// fsync after every write command
+    Always,
+    /// fsync once per second from a background task
+    Everysec,
+    /// leave flushing to the operating system
+    No,
+}
+
+pub struct AofWriter {
+    path: String,
+    file: File,
+    fsync: AppendFsync,
+    // set when there are appended bytes that have not been fsync'd yet
+    dirty: bool,
+    // commands fed while a BGREWRITEAOF is in progress, appended to the
+    // rewritten file once it is complete
+    rewrite_buffer: Option<Vec<RedisValue>>,
+}
+
+lazy_static::lazy_static! {
+    static ref AOF_WRITER: Mutex<Option<AofWriter>> = Mutex::new(None);
+}
+
+impl AofWriter {
+    pub fn open(path: &str, fsync: AppendFsync) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AofWriter {
+            path: path.to_owned(),
+            file,
+            fsync,
+            dirty: false,
+            rewrite_buffer: None,
+        })
+    }
+
+    /// Appends a write command, in its RESP2 form, to the end of the file.
+    pub fn append(&mut self, command: RedisValue) -> Result<()> {
+        if let Some(buffer) = self.rewrite_buffer.as_mut() {
+            buffer.push(command.clone());
+        }
+        self.file
+            .write_all(command.serialize(Protocol::Resp2).as_bytes())?;
+        if self.fsync == AppendFsync::Always {
+            self.file.sync_data()?;
+        } else {
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// A handle to fsync the appended bytes through, or `None` when there
+    /// are none. The caller is expected to fsync it outside the writer lock.
+    fn take_dirty(&mut self) -> Result<Option<File>> {
+        if !self.dirty {
+            return Ok(None);
+        }
+        let file = self.file.try_clone()?;
+        self.dirty = false;
+        Ok(Some(file))
+    }
+}
+
+/// Opens the AOF for appending. Must be called after `load` so replayed
+/// commands are not written back to the file.
+pub fn init(path: &str, fsync: AppendFsync) -> Result<()> {
+    *AOF_WRITER.lock().unwrap() = Some(AofWriter::open(path, fsync)?);
+
+    if fsync == AppendFsync::Everysec {
+        tokio::spawn(async {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                if let Err(e) = fsync_appended().await {
+                    warning!("AOF fsync failed: {:?}", e);
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// fsyncs whatever was appended since the last fsync. Writers feed the AOF
+/// while holding the dataset lock, so the blocking call runs on a cloned
+/// handle, off the runtime and outside the writer lock. Returns false when
+/// appendonly is disabled.
+async fn fsync_appended() -> Result<bool> {
+    let file = match AOF_WRITER.lock().unwrap().as_mut() {
+        Some(writer) => writer.take_dirty()?,
+        None => return Ok(false),
+    };
+    if let Some(file) = file {
+        if let Err(e) = tokio::task::spawn_blocking(move || file.sync_data()).await? {
+            // left for the next attempt
+            if let Some(writer) = AOF_WRITER.lock().unwrap().as_mut() {
+                writer.dirty = true;
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(true)
+}
+
+/// Feeds a write command to the AOF. A no-op when appendonly is disabled.
+pub fn feed(command: RedisValue) -> Result<()> {
+    if let Some(writer) = AOF_WRITER.lock().unwrap().as_mut() {
+        writer.append(command)?;
+    }
+    Ok(())
+}
+
+/// Makes everything appended so far durable, whatever the fsync policy.
+/// Returns false when appendonly is disabled.
+pub async fn sync() -> Result<bool> {
+    fsync_appended().await
+}
+
+/// Rewrites the AOF in a background task so it holds only the commands
+/// needed to rebuild the current dataset. `dataset` is called once buffering
+/// has started, so any write it misses is appended after the rewrite.
+pub fn rewrite_in_background<F>(dataset: F) -> Result<()>
+where
+    F: FnOnce() -> Vec<RedisValue>,
+{
+    let path = {
+        let mut guard = AOF_WRITER.lock().unwrap();
+        let writer = guard
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Append only file is disabled"))?;
+        if writer.rewrite_buffer.is_some() {
+            return Err(anyhow::anyhow!(
+                "Background append only file rewriting already in progress"
+            ));
+        }
+        writer.rewrite_buffer = Some(vec![]);
+        writer.path.clone()
+    };
+    let commands = dataset();
+
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = rewrite(&path, commands) {
+            warning!("Background AOF rewrite failed: {:?}", e);
+            if let Some(writer) = AOF_WRITER.lock().unwrap().as_mut() {
+                writer.rewrite_buffer = None;
+            }
+        }
+    });
+    Ok(())
+}
+
+fn rewrite(path: &str, commands: Vec<RedisValue>) -> Result<()> {
+    let tmp_path = format!("{}.rewrite", path);
+    let mut tmp = File::create(&tmp_path)?;
+    for command in commands {
+        tmp.write_all(command.serialize(Protocol::Resp2).as_bytes())?;
+    }
+
+    // hold the writer for the swap so no write lands in the old file
+    let mut guard = AOF_WRITER.lock().unwrap();
+    let writer = guard
+        .as_mut()
+        .ok_or_else(|| anyhow::anyhow!("Append only file is disabled"))?;
+    for command in writer.rewrite_buffer.take().unwrap_or_default() {
+        tmp.write_all(command.serialize(Protocol::Resp2).as_bytes())?;
+    }
+    tmp.sync_all()?;
+    std::fs::rename(&tmp_path, &writer.path)?;
+    // the rename is only durable once the directory holding it is synced
+    let dir = Path::new(&writer.path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()?;
+    writer.file = OpenOptions::new().append(true).open(&writer.path)?;
+    writer.dirty = false;
+    Ok(())
+}
+
+/// Reads every command stored in the AOF at `path`, in order. A missing file
+/// is treated as an empty one. A command cut off at the end of the file, as
+/// left by a crash mid-write, is truncated away, like Redis does with
+/// `aof-load-truncated yes`.
+pub fn load(path: &str) -> Result<Vec<RedisValue>> {
+    let mut contents = vec![];
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut contents)?;
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut commands = vec![];
+    let mut offset = 0;
+    while offset < contents.len() {
+        match parse_message(&contents[offset..]) {
+            Ok((command, len)) => {
+                commands.push(command);
+                offset += len;
+            }
+            Err(e) if e.is::<Incomplete>() => {
+                warning!(
+                    "The AOF ends in an incomplete command; truncating it from {} to {} bytes",
+                    contents.len(),
+                    offset
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(offset as u64)?;
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_truncates_an_incomplete_last_command() {
+        let path = std::env::temp_dir().join(format!("aof-truncated-{}.aof", std::process::id()));
+        let set = "*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n";
+        std::fs::write(&path, format!("{}*3\r\n$3\r\nSET\r\n$1\r\nk", set)).unwrap();
+
+        let commands = load(path.to_str().unwrap()).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), set);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
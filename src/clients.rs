@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// A connected client, as seen by the connection task that serves it.
+/// Dropping it unregisters the client, however its connection task ends.
+pub struct Client {
+    pub id: u64,
+    pub addr: String,
+    // notified by CLIENT KILL; the connection closes once it sees it
+    pub kill: Arc<Notify>,
+}
+
+/// Which clients CLIENT KILL targets. Every filter that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct KillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    // the id of the client running CLIENT KILL, when it must not kill itself
+    pub skip: Option<u64>,
+}
+
+/// Which commands CLIENT PAUSE holds back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    Write,
+    All,
+}
+
+lazy_static::lazy_static! {
+    // set by CLIENT PAUSE: until when, and for which commands
+    static ref PAUSE: Mutex<Option<(Instant, PauseMode)>> = Mutex::new(None);
+    static ref CLIENTS: Mutex<HashMap<u64, (String, Arc<Notify>)>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Registers a new connection from `addr` and hands out its client id.
+pub fn register(addr: &str) -> Client {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let kill = Arc::new(Notify::new());
+    CLIENTS
+        .lock()
+        .unwrap()
+        .insert(id, (addr.to_owned(), kill.clone()));
+    Client {
+        id,
+        addr: addr.to_owned(),
+        kill,
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        CLIENTS.lock().unwrap().remove(&self.id);
+        crate::replication::remove_replica(self.id);
+    }
+}
+
+/// Signals every client matching `filter` to close, returning how many did.
+pub fn kill(filter: &KillFilter) -> usize {
+    let clients = CLIENTS.lock().unwrap();
+    let mut killed = 0;
+    for (id, (addr, kill)) in clients.iter() {
+        let matches = filter.id.is_none_or(|target| target == *id)
+            && filter.addr.as_ref().is_none_or(|target| target == addr)
+            && filter.skip != Some(*id);
+        if matches {
+            kill.notify_one();
+            killed += 1;
+        }
+    }
+    killed
+}
+
+/// Holds back commands covered by `mode` for `duration`. A pause that is
+/// already running is only ever extended, never shortened.
+pub fn pause(duration: Duration, mode: PauseMode) {
+    let mut pause = PAUSE.lock().unwrap();
+    let until = Instant::now() + duration;
+    *pause = match *pause {
+        Some((current, current_mode)) if current > Instant::now() => Some((
+            current.max(until),
+            if current_mode == PauseMode::All {
+                PauseMode::All
+            } else {
+                mode
+            },
+        )),
+        _ => Some((until, mode)),
+    };
+}
+
+/// How much longer a command must wait before it may run, if it is paused.
+pub fn paused_for(is_write: bool) -> Option<Duration> {
+    match *PAUSE.lock().unwrap() {
+        Some((until, mode)) if mode == PauseMode::All || is_write => {
+            until.checked_duration_since(Instant::now())
+        }
+        _ => None,
+    }
+}
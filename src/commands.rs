@@ -0,0 +1,507 @@
+//! The table of every command the server knows: its arity, flags, key
+//! positions and ACL category.
+
+use crate::resp::RedisValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    Read,
+    Write,
+    Admin,
+    Connection,
+}
+
+impl Category {
+    pub const ALL: [Category; 4] = [
+        Category::Read,
+        Category::Write,
+        Category::Admin,
+        Category::Connection,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Read => "read",
+            Category::Write => "write",
+            Category::Admin => "admin",
+            Category::Connection => "connection",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::ALL
+            .into_iter()
+            .find(|category| category.name() == name.to_lowercase())
+    }
+}
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// number of arguments including the command name; negative means "at
+    /// least" that many
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    /// positions of the first and last key argument and the step between
+    /// keys, all 0 for commands that take no keys
+    pub first_key: i64,
+    pub last_key: i64,
+    pub key_step: i64,
+    pub category: Category,
+    /// for container commands, the usage and description of each subcommand,
+    /// as listed by `<command> HELP`
+    pub subcommands: &'static [(&'static str, &'static str)],
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "acl",
+        arity: -2,
+        flags: &["admin", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[
+            (
+                "SETUSER <username> [<rule> ...]",
+                "Create or modify a user with the specified rules.",
+            ),
+            ("WHOAMI", "Return the current connection username."),
+        ],
+    },
+    CommandSpec {
+        name: "auth",
+        arity: -2,
+        flags: &[
+            "noscript",
+            "loading",
+            "stale",
+            "fast",
+            "no_auth",
+            "skip_monitor",
+            "skip_slowlog",
+        ],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "bgrewriteaof",
+        arity: 1,
+        flags: &["admin", "noscript"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "bitpos",
+        arity: -3,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Read,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "client",
+        arity: -2,
+        flags: &["loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[
+            ("ID", "Return the ID of the current connection."),
+            ("KILL <ip:port>", "Kill connection made from <ip:port>."),
+            (
+                "KILL <option> <value> [<option> <value> [...]]",
+                "Kill connections. Options are: ID <client-id>, ADDR <ip:port>, SKIPME (YES|NO).",
+            ),
+            (
+                "PAUSE <timeout> [WRITE|ALL]",
+                "Suspend all, or just write, clients for <timeout> milliseconds.",
+            ),
+        ],
+    },
+    CommandSpec {
+        name: "cluster",
+        arity: -2,
+        flags: &["loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[
+            (
+                "INFO",
+                "Return information about the cluster; always reports it as disabled.",
+            ),
+            ("MYID", "Return the node id."),
+            ("SHARDS", "Return the cluster shards; always empty."),
+            ("SLOTS", "Return the slot ranges; always empty."),
+        ],
+    },
+    CommandSpec {
+        name: "command",
+        arity: -1,
+        flags: &["loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[
+            (
+                "INFO <command-name> [<command-name> ...]",
+                "Return details about the given commands.",
+            ),
+            (
+                "LIST [FILTERBY (MODULE <module-name>|ACLCAT <category>|PATTERN <pattern>)]",
+                "Return a list of all commands in this Redis server.",
+            ),
+        ],
+    },
+    CommandSpec {
+        name: "debug",
+        arity: -2,
+        flags: &["admin", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[
+            (
+                "CHANGE-REPL-ID",
+                "Change the replication id so replicas have to fully resync.",
+            ),
+            (
+                "OBJECT <key>",
+                "Show low level info about the <key> and associated value.",
+            ),
+            (
+                "RESET-STATS",
+                "Zero the counters reported by INFO stats.",
+            ),
+            (
+                "SET-ACTIVE-EXPIRE <0|1>",
+                "Disable or enable the removal of expired keys.",
+            ),
+            (
+                "SLEEP <seconds> [LOCAL]",
+                "Stop the server for <seconds>, or with LOCAL just this connection. Decimals allowed.",
+            ),
+            (
+                "STRINGMATCH-LEN <pattern> <string>",
+                "Return 1 if <string> matches the glob-style <pattern>, 0 otherwise.",
+            ),
+        ],
+    },
+    CommandSpec {
+        name: "echo",
+        arity: 2,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "flushall",
+        arity: -1,
+        flags: &["write"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Write,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "flushdb",
+        arity: -1,
+        flags: &["write"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Write,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "get",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Read,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "hello",
+        arity: -1,
+        flags: &[
+            "noscript",
+            "loading",
+            "stale",
+            "fast",
+            "no_auth",
+            "skip_monitor",
+            "skip_slowlog",
+        ],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "info",
+        arity: -1,
+        flags: &["loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "lcs",
+        arity: -3,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 2,
+        key_step: 1,
+        category: Category::Read,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "memory",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Read,
+        subcommands: &[
+            ("DOCTOR", "Return memory problems reports."),
+            (
+                "STATS",
+                "Return information about the memory usage of the server.",
+            ),
+            (
+                "USAGE <key> [SAMPLES <count>]",
+                "Return memory in bytes used by <key> and its value.",
+            ),
+        ],
+    },
+    CommandSpec {
+        name: "monitor",
+        arity: 1,
+        flags: &["admin", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "ping",
+        arity: -1,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "quit",
+        arity: -1,
+        flags: &["noscript", "loading", "stale", "fast", "no_auth"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "replconf",
+        arity: -1,
+        flags: &["admin", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "set",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Write,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "slowlog",
+        arity: -2,
+        flags: &["admin", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Admin,
+        subcommands: &[
+            (
+                "GET [<count>]",
+                "Return top <count> entries from the slowlog (default: 10, -1 means all).",
+            ),
+            ("LEN", "Return the length of the slowlog."),
+            ("RESET", "Reset the slowlog."),
+        ],
+    },
+    CommandSpec {
+        name: "waitaof",
+        arity: 4,
+        flags: &["noscript"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Connection,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "xadd",
+        arity: -5,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Write,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "xdel",
+        arity: -3,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Write,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "xlen",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Read,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "xrange",
+        arity: -4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Read,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "xread",
+        arity: -4,
+        flags: &["readonly", "blocking"],
+        first_key: 0,
+        last_key: 0,
+        key_step: 0,
+        category: Category::Read,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "xrevrange",
+        arity: -4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Read,
+        subcommands: &[],
+    },
+    CommandSpec {
+        name: "xtrim",
+        arity: -4,
+        flags: &["write"],
+        first_key: 1,
+        last_key: 1,
+        key_step: 1,
+        category: Category::Write,
+        subcommands: &[],
+    },
+];
+
+/// Whether the command carries `flag` in its spec.
+pub fn has_flag(name: &str, flag: &str) -> bool {
+    lookup(name).is_some_and(|spec| spec.flags.contains(&flag))
+}
+
+/// Whether `argc` arguments, counting the command name itself, fit the
+/// command's arity. Unknown commands pass, so they fail as unknown instead.
+pub fn arity_matches(name: &str, argc: usize) -> bool {
+    lookup(name).is_none_or(|spec| {
+        let argc = argc as i64;
+        if spec.arity >= 0 {
+            argc == spec.arity
+        } else {
+            argc >= -spec.arity
+        }
+    })
+}
+
+/// Looks up a command by its lowercase name.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+/// The COMMAND INFO entry for one command.
+pub fn info(spec: &CommandSpec) -> RedisValue {
+    RedisValue::Array(vec![
+        RedisValue::BulkString(spec.name.to_owned()),
+        RedisValue::Integer(spec.arity),
+        RedisValue::Array(
+            spec.flags
+                .iter()
+                .map(|flag| RedisValue::SimpleString((*flag).to_owned()))
+                .collect(),
+        ),
+        RedisValue::Integer(spec.first_key),
+        RedisValue::Integer(spec.last_key),
+        RedisValue::Integer(spec.key_step),
+        RedisValue::Array(vec![RedisValue::SimpleString(format!(
+            "@{}",
+            spec.category.name()
+        ))]),
+    ])
+}
+
+/// The `<command> HELP` reply: a usage line followed by each subcommand and
+/// its description.
+pub fn help(spec: &CommandSpec) -> RedisValue {
+    let mut lines = vec![format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        spec.name.to_uppercase()
+    )];
+    let help = [("HELP", "Print this help.")];
+    for (usage, description) in spec.subcommands.iter().chain(help.iter()) {
+        lines.push((*usage).to_owned());
+        lines.push(format!("    {}", description));
+    }
+    RedisValue::Array(lines.into_iter().map(RedisValue::SimpleString).collect())
+}
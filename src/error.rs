@@ -0,0 +1,45 @@
+//! Constructors for error replies. Client libraries dispatch on the first
+//! word of an error, so every handler should build its errors here rather
+//! than formatting the prefix by hand.
+
+use std::fmt::Display;
+
+use crate::resp::RedisValue;
+
+/// A generic `ERR` reply.
+pub fn err(message: impl Display) -> RedisValue {
+    RedisValue::Error(format!("ERR {}", message))
+}
+
+/// Sent for any command other than AUTH on a connection that has not
+/// authenticated yet.
+pub fn no_auth() -> RedisValue {
+    RedisValue::Error("NOAUTH Authentication required.".to_owned())
+}
+
+/// HELLO on an unauthenticated connection is only allowed with its AUTH option.
+pub fn no_auth_hello() -> RedisValue {
+    RedisValue::Error("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".to_owned())
+}
+
+pub fn wrong_pass() -> RedisValue {
+    RedisValue::Error("WRONGPASS invalid username-password pair or user is disabled.".to_owned())
+}
+
+pub fn no_perm(user: &str, command: &str) -> RedisValue {
+    RedisValue::Error(format!(
+        "NOPERM User {} has no permissions to run the '{}' command",
+        user, command
+    ))
+}
+
+pub fn no_proto() -> RedisValue {
+    RedisValue::Error("NOPROTO unsupported protocol version".to_owned())
+}
+
+/// Sent when a command meets a key holding a different type than it works on.
+pub fn wrong_type() -> RedisValue {
+    RedisValue::Error(
+        "WRONGTYPE Operation against a key holding the wrong kind of value".to_owned(),
+    )
+}
@@ -0,0 +1,223 @@
+//! LCS: the longest common subsequence of two strings.
+
+use crate::error;
+use crate::resp::RedisValue;
+
+// like Redis, refuse to build a table larger than proto-max-bulk-len
+const MAX_TABLE_BYTES: usize = 512 * 1024 * 1024;
+
+/// The options of `LCS key1 key2 [LEN] [IDX [MINMATCHLEN n] [WITHMATCHLEN]]`.
+#[derive(Debug, Clone, Default)]
+pub struct LcsOptions {
+    pub len: bool,
+    pub idx: bool,
+    // matching ranges shorter than this are left out of the IDX reply
+    pub min_match_len: usize,
+    pub with_match_len: bool,
+}
+
+/// One run of consecutive bytes common to both strings, as inclusive
+/// (start, end) offsets into each of them.
+struct Match {
+    a: (usize, usize),
+    b: (usize, usize),
+}
+
+impl Match {
+    fn len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+/// Computes the LCS of `a` and `b` and formats it as the LCS reply.
+pub fn reply(a: &[u8], b: &[u8], options: &LcsOptions) -> RedisValue {
+    let table_bytes = (a.len() + 1)
+        .checked_mul(b.len() + 1)
+        .and_then(|cells| cells.checked_mul(std::mem::size_of::<usize>()));
+    if table_bytes.is_none_or(|bytes| bytes > MAX_TABLE_BYTES) {
+        return error::err(
+            "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len",
+        );
+    }
+
+    let (subsequence, matches) = lcs(a, b);
+    if options.len {
+        return RedisValue::Integer(subsequence.len() as i64);
+    }
+    if !options.idx {
+        // bytes are compared, so the subsequence can end up splitting a
+        // multi-byte character, which a string value cannot hold
+        return match String::from_utf8(subsequence) {
+            Ok(subsequence) => RedisValue::BulkString(subsequence),
+            Err(_) => error::err("The LCS is not valid UTF-8, use LEN or IDX instead"),
+        };
+    }
+
+    let range = |(start, end): (usize, usize)| {
+        RedisValue::Array(vec![
+            RedisValue::Integer(start as i64),
+            RedisValue::Integer(end as i64),
+        ])
+    };
+    let matches = matches
+        .into_iter()
+        .filter(|m| m.len() >= options.min_match_len)
+        .map(|m| {
+            let mut item = vec![range(m.a), range(m.b)];
+            if options.with_match_len {
+                item.push(RedisValue::Integer(m.len() as i64));
+            }
+            RedisValue::Array(item)
+        })
+        .collect();
+    RedisValue::Map(vec![
+        (
+            RedisValue::BulkString("matches".to_owned()),
+            RedisValue::Array(matches),
+        ),
+        (
+            RedisValue::BulkString("len".to_owned()),
+            RedisValue::Integer(subsequence.len() as i64),
+        ),
+    ])
+}
+
+/// Returns the longest common subsequence along with the runs it is made
+/// of. Like Redis, the runs are listed from the end of the strings back.
+fn lcs(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<Match>) {
+    // table[i][j] is the LCS length of a[..i] and b[..j]
+    let mut table = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i][j] = if a[i - 1] == b[j - 1] {
+                table[i - 1][j - 1] + 1
+            } else {
+                table[i - 1][j].max(table[i][j - 1])
+            };
+        }
+    }
+
+    // walk back from the end, growing the current run while matches are
+    // consecutive in both strings
+    let mut subsequence = Vec::with_capacity(table[a.len()][b.len()]);
+    let mut matches = vec![];
+    let mut current: Option<Match> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            subsequence.push(a[i - 1]);
+            match current.as_mut() {
+                Some(m) if m.a.0 == i && m.b.0 == j => {
+                    m.a.0 -= 1;
+                    m.b.0 -= 1;
+                }
+                _ => {
+                    matches.extend(current.take());
+                    current = Some(Match {
+                        a: (i - 1, i - 1),
+                        b: (j - 1, j - 1),
+                    });
+                }
+            }
+            i -= 1;
+            j -= 1;
+        } else {
+            matches.extend(current.take());
+            if table[i - 1][j] > table[i][j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+        }
+    }
+    matches.extend(current);
+    subsequence.reverse();
+    (subsequence, matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declines_a_subsequence_that_splits_a_character() {
+        let options = LcsOptions::default();
+        assert_eq!(
+            reply("ohmytext".as_bytes(), "mynewtext".as_bytes(), &options),
+            RedisValue::BulkString("mytext".to_owned())
+        );
+        assert!(matches!(
+            reply("é".as_bytes(), "è".as_bytes(), &options),
+            RedisValue::Error(_)
+        ));
+        let len = LcsOptions {
+            len: true,
+            ..LcsOptions::default()
+        };
+        assert_eq!(
+            reply("é".as_bytes(), "è".as_bytes(), &len),
+            RedisValue::Integer(1)
+        );
+    }
+
+    #[test]
+    fn lists_matches_like_the_redis_docs() {
+        let range = |start, end| {
+            RedisValue::Array(vec![RedisValue::Integer(start), RedisValue::Integer(end)])
+        };
+        let reply_with = |matches, len| {
+            RedisValue::Map(vec![
+                (
+                    RedisValue::BulkString("matches".to_owned()),
+                    RedisValue::Array(matches),
+                ),
+                (
+                    RedisValue::BulkString("len".to_owned()),
+                    RedisValue::Integer(len),
+                ),
+            ])
+        };
+
+        let idx = LcsOptions {
+            idx: true,
+            ..LcsOptions::default()
+        };
+        assert_eq!(
+            reply(b"ohmytext", b"mynewtext", &idx),
+            reply_with(
+                vec![
+                    RedisValue::Array(vec![range(4, 7), range(5, 8)]),
+                    RedisValue::Array(vec![range(2, 3), range(0, 1)]),
+                ],
+                6
+            )
+        );
+
+        let long_matches = LcsOptions {
+            idx: true,
+            min_match_len: 4,
+            with_match_len: true,
+            ..LcsOptions::default()
+        };
+        assert_eq!(
+            reply(b"ohmytext", b"mynewtext", &long_matches),
+            reply_with(
+                vec![RedisValue::Array(vec![
+                    range(4, 7),
+                    range(5, 8),
+                    RedisValue::Integer(4),
+                ])],
+                6
+            )
+        );
+    }
+
+    #[test]
+    fn refuses_an_oversized_table() {
+        let long = vec![b'a'; 100_000];
+        assert!(matches!(
+            reply(&long, &long, &LcsOptions::default()),
+            RedisValue::Error(_)
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! Leveled logging to stderr, in Redis's log line format:
+//! `pid:M 15 Oct 2026 10:00:00.123 * message`.
+
+use std::fmt::Arguments;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+/// How much the server logs, from most to least verbose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Level {
+    /// every command and reply
+    Debug,
+    /// connections opening and closing
+    Verbose,
+    /// startup and other notable events
+    Notice,
+    /// only problems
+    Warning,
+}
+
+impl Level {
+    // the character Redis puts before the message
+    fn mark(self) -> char {
+        match self {
+            Level::Debug => '.',
+            Level::Verbose => '-',
+            Level::Notice => '*',
+            Level::Warning => '#',
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Notice as u8);
+
+/// Sets the lowest level that gets logged. Called once from `main`.
+pub fn configure(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log(level: Level, message: Arguments) {
+    if enabled(level) {
+        eprintln!(
+            "{}:M {} {} {}",
+            std::process::id(),
+            timestamp(),
+            level.mark(),
+            message
+        );
+    }
+}
+
+// the current UTC time as `15 Oct 2026 10:00:00.123`
+fn timestamp() -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{} {} {} {:02}:{:02}:{:02}.{:03}",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+// days since 1970-01-01 to a (year, month, day) date, after Howard Hinnant's
+// `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Debug, format_args!($($arg)*))
+    };
+}
+
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Verbose, format_args!($($arg)*))
+    };
+}
+
+macro_rules! notice {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Notice, format_args!($($arg)*))
+    };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::logging::log($crate::logging::Level::Warning, format_args!($($arg)*))
+    };
+}
@@ -1,28 +1,220 @@
+// first, so its macros are in scope for the modules after it
+#[macro_use]
+mod logging;
+
+mod acl;
+mod aof;
+mod clients;
+mod commands;
+mod error;
+mod lcs;
+mod memory;
+mod monitor;
+mod replication;
 mod resp;
+mod server;
+mod slowlog;
+mod stats;
+mod stream;
+mod util;
 
-use anyhow::{Error, Ok, Result};
+use anyhow::{Ok, Result};
 
-use resp::{parse_int_with_sign, RedisValue};
-use std::time::SystemTime;
-use tokio::net::{TcpListener, TcpStream};
+use resp::{parse_int_with_sign, Protocol, RedisValue};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpSocket, UnixListener};
+use tokio::sync::broadcast::error::RecvError;
 
 #[derive(Debug, Clone)]
 enum RedisCommand {
     Echo(RedisValue),
     Ping,
-    Set(RedisValue, RedisValue),
-    SetTimeout(RedisValue, RedisValue, RedisValue),
+    Set(RedisValue, RedisValue, SetOptions),
     Get(RedisValue),
     Info(RedisValue),
+    BgRewriteAof,
+    // numlocal, numreplicas and the timeout in milliseconds
+    WaitAof(i64, i64, i64),
+    BitPos(RedisValue, u8, Option<BitRange>),
+    Memory(MemorySubcommand),
+    Auth(Option<String>, String),
+    Acl(AclSubcommand),
+    Debug(DebugSubcommand),
+    Command(CommandSubcommand),
+    // protocol version, and the AUTH username and password
+    Hello(Option<i64>, Option<(String, String)>),
+    Slowlog(SlowlogSubcommand),
+    Monitor,
+    Quit,
+    Replconf(Vec<ReplconfOption>),
+    Cluster(ClusterSubcommand),
+    // FLUSHALL and FLUSHDB, which are the same with a single database
+    Flush(FlushMode),
+    Client(ClientSubcommand),
+    Lcs(RedisValue, RedisValue, lcs::LcsOptions),
+    // key, id and the field/value pairs
+    XAdd(RedisValue, stream::XAddId, Vec<(String, String)>),
+    XLen(RedisValue),
+    XDel(RedisValue, Vec<stream::StreamId>),
+    XTrim(RedisValue, stream::TrimStrategy),
+    // key, the inclusive start and end ids, COUNT, and whether the entries
+    // come newest first (XREVRANGE)
+    XRange(
+        RedisValue,
+        stream::StreamId,
+        stream::StreamId,
+        Option<usize>,
+        bool,
+    ),
+    // COUNT, BLOCK in milliseconds, and each key with the id to read past;
+    // `None` stands for `$`, the stream's last id when the command runs
+    XRead(
+        Option<usize>,
+        Option<u64>,
+        Vec<(RedisValue, Option<stream::StreamId>)>,
+    ),
+    // HELP of the named container command
+    Help(String),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+enum FlushMode {
+    #[default]
+    Sync,
+    // drop the old dataset in a background task
+    Async,
+}
+
+// single-node answers, so cluster-aware clients can run against a
+// standalone server
+#[derive(Debug, Clone)]
+enum ClusterSubcommand {
+    Info,
+    Slots,
+    Shards,
+    MyId,
+}
+
+#[derive(Debug, Clone)]
+enum ReplconfOption {
+    ListeningPort(u16),
+    Capa(String),
+}
+
+#[derive(Debug, Clone)]
+enum SlowlogSubcommand {
+    Get(Option<i64>),
+    Len,
+    Reset,
+}
+
+#[derive(Debug, Clone)]
+enum CommandSubcommand {
+    Info(Vec<String>),
+    List(Option<CommandFilter>),
+}
+
+/// The FILTERBY clause of COMMAND LIST.
+#[derive(Debug, Clone)]
+enum CommandFilter {
+    // the module name is not kept, as there are no modules to match it
+    Module,
+    AclCat(String),
+    Pattern(String),
+}
+
+#[derive(Debug, Clone)]
+enum DebugSubcommand {
+    SetActiveExpire(bool),
+    ChangeReplId,
+    // how long, and whether every connection is held up rather than just
+    // this one
+    Sleep(Duration, bool),
+    // pattern and string
+    StringMatchLen(String, String),
+    ResetStats,
+    Object(RedisValue),
+}
+
+#[derive(Debug, Clone)]
+enum ClientSubcommand {
+    Id,
+    // the filter, whether it was the legacy `CLIENT KILL addr` form, and
+    // whether the calling client is spared (SKIPME)
+    Kill(clients::KillFilter, bool, bool),
+    Pause(Duration, clients::PauseMode),
+}
+
+#[derive(Debug, Clone)]
+enum AclSubcommand {
+    WhoAmI,
+    SetUser(String, Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+enum MemorySubcommand {
+    Usage(RedisValue),
+    Doctor,
+    Stats,
+}
+
+#[derive(Debug, Clone, Default)]
+struct SetOptions {
+    expiry: SetExpiry,
+    // GET: reply with the previous value instead of OK
+    get: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+enum SetExpiry {
+    /// plain SET drops any existing expiry
+    #[default]
+    Clear,
+    Px(RedisValue),
+    // an absolute deadline in unix milliseconds
+    PxAt(i64),
+    KeepTtl,
+}
+
+/// The optional `start [end [BYTE|BIT]]` arguments of BITPOS.
+#[derive(Debug, Clone)]
+struct BitRange {
+    start: i64,
+    end: Option<i64>,
+    // offsets count bits rather than the default bytes
+    in_bits: bool,
 }
 
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+// value, plus the (timeout in ms, inserted at) pair for keys set with PX
+type StoredEntry = (StoredValue, Option<(RedisValue, SystemTime)>);
+
+/// What a key holds.
+#[derive(Debug, Clone)]
+enum StoredValue {
+    String(RedisValue),
+    Stream(stream::Stream),
+}
 
 lazy_static::lazy_static! {
-    static ref GLOBAL_HASHMAP: Mutex<HashMap<RedisValue, (RedisValue, Option<(RedisValue, SystemTime)>)>> = Mutex::new(HashMap::new());
+    static ref GLOBAL_HASHMAP: Mutex<HashMap<RedisValue, StoredEntry>> = Mutex::new(HashMap::new());
+    // held shared while a command runs; DEBUG SLEEP takes it exclusively to
+    // stall the whole server, the way it does a single-threaded Redis
+    static ref EXECUTION: tokio::sync::RwLock<()> = tokio::sync::RwLock::new(());
 }
 
+// the Redis version this server reports being compatible with
+const REDIS_VERSION: &str = "7.2.0";
+
+// DEBUG SET-ACTIVE-EXPIRE: when off, expired keys are hidden from clients but
+// left in the map, so tests can observe them
+static ACTIVE_EXPIRE: AtomicBool = AtomicBool::new(true);
+
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -31,112 +223,869 @@ struct Args {
     /// The port number to use
     #[arg(short, long, default_value_t = 6379)]
     port: u16,
+
+    /// Require clients to AUTH with this password before running commands
+    #[arg(long)]
+    requirepass: Option<String>,
+
+    /// Log every write command to the append-only file and replay it on startup
+    #[arg(long, default_value = "no", value_parser = ["yes", "no"])]
+    appendonly: String,
+
+    /// The name of the append-only file
+    #[arg(long, default_value = "appendonly.aof")]
+    appendfilename: String,
+
+    /// How often the append-only file is fsync'd
+    #[arg(long, value_enum, default_value_t = aof::AppendFsync::Everysec)]
+    appendfsync: aof::AppendFsync,
+
+    /// Log commands slower than this many microseconds to the slowlog (negative disables it)
+    #[arg(long, default_value_t = 10000, allow_negative_numbers = true)]
+    slowlog_log_slower_than: i64,
+
+    /// The number of entries the slowlog keeps
+    #[arg(long, default_value_t = 128)]
+    slowlog_max_len: usize,
+
+    /// How many lines a MONITOR client may have pending before it is disconnected
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+    client_output_buffer_limit: u32,
+
+    /// How much to log to stderr
+    #[arg(long, value_enum, default_value_t = logging::Level::Notice)]
+    loglevel: logging::Level,
+
+    /// Also accept connections on a Unix domain socket at this path
+    #[arg(long)]
+    unixsocket: Option<String>,
+
+    /// The length of the queue of connections waiting to be accepted
+    #[arg(long, default_value_t = 511)]
+    tcp_backlog: u32,
+
+    /// Send TCP keepalive probes to idle clients, so dead peers are dropped (0 disables them). Tokio
+    /// cannot set the idle time, so the probes follow the kernel's schedule whatever the value
+    #[arg(long, default_value_t = 300)]
+    tcp_keepalive: u64,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    dbg!(args.port);
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", args.port)).await?;
+    logging::configure(args.loglevel);
+    server::init(args.port);
+    acl::init(args.requirepass.clone());
+    slowlog::configure(args.slowlog_log_slower_than, args.slowlog_max_len);
+    monitor::configure(args.client_output_buffer_limit as usize);
 
-    loop {
-        let (stream, _) = listener.accept().await?;
+    if args.appendonly == "yes" {
+        // rebuild the dataset before accepting any connections
+        for command in aof::load(&args.appendfilename)? {
+            handle_command(to_command(extract_command(command)?)?);
+        }
+        aof::init(&args.appendfilename, args.appendfsync)?;
+    }
+
+    let socket = TcpSocket::new_v4()?;
+    // as a plain bind would, so a restart does not wait out TIME_WAIT
+    socket.set_reuseaddr(true)?;
+    // accepted connections inherit it, so a peer that vanished without
+    // closing, such as a replica behind a dropped link, is dropped in time
+    socket.set_keepalive(args.tcp_keepalive > 0)?;
+    socket.bind(([0, 0, 0, 0], args.port).into())?;
+    let listener = socket.listen(args.tcp_backlog)?;
+    notice!("Ready to accept connections tcp on port {}", args.port);
+
+    if let Some(path) = args.unixsocket {
+        // a socket file left behind by a previous run would make bind fail
+        let _ = std::fs::remove_file(&path);
+        let unix_listener = UnixListener::bind(&path)?;
         tokio::spawn(async move {
-            let _ = handle_connection(stream).await;
+            loop {
+                match unix_listener.accept().await {
+                    // unix socket clients have no port, so they show as <path>:0
+                    Result::Ok((stream, _)) => spawn_connection(stream, format!("{}:0", path)),
+                    Result::Err(e) => warning!("Unix socket accept failed: {:?}", e),
+                }
+            }
         });
     }
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        // replies are already written whole, so Nagle would only delay them
+        if let Result::Err(e) = stream.set_nodelay(true) {
+            warning!(
+                "Dropping connection from {}: TCP_NODELAY failed: {:?}",
+                peer,
+                e
+            );
+            continue;
+        }
+        spawn_connection(stream, peer.to_string());
+    }
+}
+
+fn spawn_connection<S>(stream: S, addr: String)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let client = clients::register(&addr);
+        stats::connection_received();
+        verbose!("Accepted {} as client id={}", addr, client.id);
+        if let Result::Err(e) = handle_connection(stream, &client).await {
+            verbose!("Client id={} addr={} failed: {:?}", client.id, addr, e);
+        }
+        verbose!("Client id={} addr={} closed", client.id, addr);
+    });
+}
+
+/// What a connection carries from one command to the next.
+struct ConnState {
+    id: u64,
+    addr: String,
+    // notified by CLIENT KILL
+    kill: Arc<Notify>,
+    // whether this connection has passed AUTH, or needed none
+    authenticated: bool,
+    // the ACL user commands run as
+    user: String,
+    protocol: Protocol,
+    // set once the peer introduces itself as a replica with REPLCONF
+    replica: Option<replication::ReplicaInfo>,
+    // set by MONITOR: after its reply the connection only streams the feed
+    monitor: bool,
+    // set by QUIT: the connection closes once its reply is sent
+    quit: bool,
+    // how long the running command has spent blocked, as in XREAD BLOCK,
+    // which the slowlog leaves out
+    blocked: Duration,
+}
+
+impl ConnState {
+    fn new(client: &clients::Client) -> ConnState {
+        ConnState {
+            id: client.id,
+            addr: client.addr.clone(),
+            kill: client.kill.clone(),
+            authenticated: !acl::default_user_requires_auth(),
+            user: acl::DEFAULT_USER.to_owned(),
+            protocol: Protocol::Resp2,
+            replica: None,
+            monitor: false,
+            quit: false,
+            blocked: Duration::ZERO,
+        }
+    }
 }
 
 // *2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n
-async fn handle_connection(stream: TcpStream) -> Result<()> {
+async fn handle_connection<S>(stream: S, client: &clients::Client) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     let mut handler = resp::RespHandler::new(stream);
+    let mut conn = ConnState::new(client);
 
     loop {
-        let value = handler.read_value().await?;
-        eprintln!("Got value {:?}", value);
+        let value = tokio::select! {
+            value = handler.read_value() => value?,
+            _ = conn.kill.notified() => break Ok(()),
+        };
 
         let response = if let Some(v) = value {
-            match to_command(extract_command(v)?) {
-                Result::Ok(RedisCommand::Echo(args)) => args,
-                Result::Ok(RedisCommand::Ping) => RedisValue::SimpleString("PONG".to_owned()),
-                Result::Ok(RedisCommand::Set(key, value)) => {
-                    let _ = handle_command(RedisCommand::Set(key, value));
-                    // response to be sent to redis-client
-                    RedisValue::SimpleString("OK".to_owned())
-                }
-                Result::Ok(RedisCommand::Get(key)) => {
-                    if let Some(value) = handle_command(RedisCommand::Get(key)) {
-                        value
+            let (name, args) = extract_command(v.clone())?;
+            let name = name.to_lowercase();
+            // what is logged and shown to MONITOR, without ACL SETUSER passwords
+            let shown = acl::redact(&v);
+            // AUTH and HELLO carry passwords, so only their name is logged
+            if commands::has_flag(&name, "skip_monitor") {
+                debug!("Command from client id={}: {} (redacted)", conn.id, name);
+            } else {
+                debug!("Command from client id={}: {:?}", conn.id, shown);
+            }
+            let permitted = acl::can_run(&conn.user, &name);
+            let command = to_command((name.clone(), args));
+            // CLIENT itself is never paused, so a pause can be inspected
+            if name != "client" {
+                let is_write = commands::has_flag(&name, "write");
+                while let Some(remaining) = clients::paused_for(is_write) {
+                    if sleep_unless_killed(remaining, &conn.kill).await {
+                        return Ok(());
+                    }
+                }
+            }
+            if command.is_ok()
+                && conn.authenticated
+                && permitted
+                && !commands::has_flag(&name, "skip_monitor")
+            {
+                monitor::feed(&shown, &conn.addr);
+            }
+            let response = match (refusal(&conn, &name), command) {
+                (Some(refusal), _) => refusal,
+                (None, Result::Ok(command)) => {
+                    // replies to earlier pipelined commands must not wait
+                    // for one that blocks
+                    if commands::has_flag(&name, "blocking") {
+                        handler.flush().await?;
+                    }
+                    stats::command_processed();
+                    dispatch_logged(&name, &shown, command, &mut conn).await?
+                }
+                (None, Result::Err(e)) => error::err(e),
+            };
+            response
+        } else {
+            break Ok(());
+        };
+        debug!("Reply to client id={}: {:?}", conn.id, response);
+        // sent along with the replies to the rest of the batch
+        handler.queue_value(response, conn.protocol);
+        if conn.monitor {
+            break stream_monitor(&mut handler, &conn).await;
+        }
+        if conn.quit {
+            break handler.flush().await;
+        }
+    }
+}
+
+/// The NOAUTH or NOPERM reply for a command the connection may not run.
+/// Commands flagged `no_auth`, such as AUTH itself, are always let through.
+fn refusal(conn: &ConnState, name: &str) -> Option<RedisValue> {
+    if commands::has_flag(name, "no_auth") {
+        None
+    } else if !conn.authenticated {
+        Some(error::no_auth())
+    } else if !acl::can_run(&conn.user, name) {
+        Some(error::no_perm(&conn.user, name))
+    } else {
+        None
+    }
+}
+
+/// Sleeps for `duration`, or until the client is killed with CLIENT KILL,
+/// returning whether it was. The kill is left pending, so the connection
+/// still closes once the command returns.
+async fn sleep_unless_killed(duration: Duration, kill: &Notify) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => false,
+        _ = kill.notified() => {
+            kill.notify_one();
+            true
+        }
+    }
+}
+
+/// Like `dispatch`, also logging the command to the slowlog if it ran for
+/// too long. Time spent blocked is not counted, as it is not execution.
+async fn dispatch_logged(
+    name: &str,
+    frame: &RedisValue,
+    command: RedisCommand,
+    conn: &mut ConnState,
+) -> Result<RedisValue> {
+    conn.blocked = Duration::ZERO;
+    let started = Instant::now();
+    let reply = dispatch(command, conn).await?;
+    // AUTH and HELLO carry passwords, which must not end up in the log
+    if !commands::has_flag(name, "skip_slowlog") {
+        let duration = started.elapsed().saturating_sub(conn.blocked);
+        slowlog::record(duration, frame, &conn.addr);
+    }
+    Ok(reply)
+}
+
+/// Runs one command and produces its reply. Everything a command does
+/// happens here, apart from reading it off and writing the reply to the
+/// socket, so commands can be run without a connection. Commands work on
+/// the global keyspace, like the rest of the server, rather than on a store
+/// passed in.
+async fn dispatch(command: RedisCommand, conn: &mut ConnState) -> Result<RedisValue> {
+    let blocks_server = matches!(
+        command,
+        RedisCommand::Debug(DebugSubcommand::Sleep(_, true))
+    );
+    let executing = if blocks_server {
+        None
+    } else {
+        Some(EXECUTION.read().await)
+    };
+    let reply = match command {
+        RedisCommand::Hello(version, auth) => {
+            let protocol = match version {
+                Some(version) => Protocol::from_version(version),
+                None => Some(conn.protocol),
+            };
+            match (protocol, auth) {
+                (None, _) => error::no_proto(),
+                (Some(_), Some((username, password)))
+                    if !acl::authenticate(&username, &password) =>
+                {
+                    error::wrong_pass()
+                }
+                (Some(protocol), auth) => {
+                    if let Some((username, _)) = auth {
+                        conn.authenticated = true;
+                        conn.user = username;
+                    }
+                    if conn.authenticated {
+                        conn.protocol = protocol;
+                        hello_reply(protocol)
                     } else {
-                        RedisValue::SimpleString("-1".to_owned())
+                        error::no_auth_hello()
                     }
                 }
-                Result::Ok(RedisCommand::SetTimeout(key, value, timeout)) => {
-                    let _ = handle_command(RedisCommand::SetTimeout(key, value, timeout));
-                    RedisValue::SimpleString("OK".to_owned())
+            }
+        }
+        RedisCommand::Auth(None, _) if !acl::default_user_requires_auth() => {
+            error::err(
+                "AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?",
+            )
+        }
+        RedisCommand::Auth(username, password) => {
+            let username = username.unwrap_or_else(|| acl::DEFAULT_USER.to_owned());
+            if acl::authenticate(&username, &password) {
+                conn.authenticated = true;
+                conn.user = username;
+                RedisValue::SimpleString("OK".to_owned())
+            } else {
+                error::wrong_pass()
+            }
+        }
+        RedisCommand::Client(ClientSubcommand::Id) => {
+            RedisValue::Integer(conn.id as i64)
+        }
+        RedisCommand::Client(ClientSubcommand::Kill(
+            mut filter,
+            legacy,
+            skip_me,
+        )) => {
+            if skip_me {
+                filter.skip = Some(conn.id);
+            }
+            let killed = clients::kill(&filter);
+            match (legacy, killed) {
+                (false, killed) => RedisValue::Integer(killed as i64),
+                (true, 0) => error::err("No such client"),
+                (true, _) => RedisValue::SimpleString("OK".to_owned()),
+            }
+        }
+        RedisCommand::Client(ClientSubcommand::Pause(duration, mode)) => {
+            clients::pause(duration, mode);
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Acl(AclSubcommand::WhoAmI) => {
+            RedisValue::BulkString(conn.user.clone())
+        }
+        RedisCommand::Acl(AclSubcommand::SetUser(username, rules)) => {
+            match acl::set_user(&username, &rules) {
+                Result::Ok(()) => RedisValue::SimpleString("OK".to_owned()),
+                Result::Err(e) => error::err(e),
+            }
+        }
+        RedisCommand::Echo(args) => args,
+        RedisCommand::Ping => RedisValue::SimpleString("PONG".to_owned()),
+        RedisCommand::Set(key, value, options) => {
+            let get = options.get;
+            let previous = handle_command(RedisCommand::Set(key, value, options));
+            // response to be sent to redis-client
+            if get {
+                previous.unwrap_or(RedisValue::Null)
+            } else {
+                RedisValue::SimpleString("OK".to_owned())
+            }
+        }
+        // commands on the keyspace; only GET replies with nothing, for a
+        // missing key
+        command @ (RedisCommand::Get(_)
+        | RedisCommand::XAdd(..)
+        | RedisCommand::XLen(_)
+        | RedisCommand::XDel(..)
+        | RedisCommand::XTrim(..)
+        | RedisCommand::XRange(..)
+        | RedisCommand::Flush(_)
+        | RedisCommand::Info(_)
+        | RedisCommand::BitPos(..)
+        | RedisCommand::Lcs(..)
+        | RedisCommand::Memory(_)) => handle_command(command).unwrap_or(RedisValue::Null),
+        RedisCommand::XRead(count, block, streams) => {
+            let keys = streams.iter().map(|(key, _)| key.clone()).collect();
+            // registered before the first look, so no entry slips by
+            let reader = block.map(|_| stream::BlockedReader::register(keys));
+            let streams = streams
+                .into_iter()
+                .map(|(key, id)| {
+                    let id = id.unwrap_or_else(|| {
+                        with_entry_mut(&key, |entry| match as_stream_mut(entry) {
+                            Result::Ok(Some(stream)) => stream.last_id,
+                            _ => stream::StreamId::MIN,
+                        })
+                    });
+                    (key, id)
+                })
+                .collect::<Vec<_>>();
+            // a blocked client must not hold up DEBUG SLEEP
+            drop(executing);
+            let deadline = block
+                .filter(|ms| *ms > 0)
+                .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
+            loop {
+                if let Some(reply) = xread(&streams, count) {
+                    break reply;
+                }
+                let Some(reader) = &reader else {
+                    break RedisValue::NullArray;
+                };
+                let waiting = Instant::now();
+                let timed_out = tokio::select! {
+                    _ = reader.wait() => false,
+                    _ = async {
+                        match deadline {
+                            Some(deadline) => tokio::time::sleep_until(deadline).await,
+                            None => std::future::pending().await,
+                        }
+                    } => true,
+                    _ = conn.kill.notified() => {
+                        // leave the kill pending, so the connection
+                        // closes as soon as this returns
+                        conn.kill.notify_one();
+                        conn.blocked += waiting.elapsed();
+                        break RedisValue::NullArray;
+                    }
+                };
+                conn.blocked += waiting.elapsed();
+                if timed_out {
+                    break RedisValue::NullArray;
                 }
+            }
+        }
+        RedisCommand::WaitAof(numlocal, numreplicas, timeout) => {
+            let local = if aof::sync().await? { 1 } else { 0 };
+            // waiting out the timeout must not hold up DEBUG SLEEP
+            drop(executing);
+            // replicas never acknowledge anything, so an unmet request
+            // can only run into the timeout; unlike Redis, a timeout
+            // of 0 replies right away instead of blocking forever
+            if (local < numlocal || numreplicas > 0) && timeout > 0 {
+                let waiting = Instant::now();
+                sleep_unless_killed(Duration::from_millis(timeout as u64), &conn.kill).await;
+                conn.blocked += waiting.elapsed();
+            }
+            RedisValue::Array(vec![RedisValue::Integer(local), RedisValue::Integer(0)])
+        }
+        RedisCommand::BgRewriteAof => {
+            // held across the snapshot on purpose, so every write lands
+            // either in it or in the rewrite buffer, never both. Clients wait
+            // while the commands are built; only the file is written in the
+            // background
+            let hashmap = GLOBAL_HASHMAP.lock().unwrap();
+            match aof::rewrite_in_background(|| dataset_as_commands(&hashmap)) {
+                Result::Ok(()) => RedisValue::SimpleString(
+                    "Background append only file rewriting started".to_owned(),
+                ),
+                Result::Err(e) => error::err(e),
+            }
+        }
 
-                Result::Ok(info_command @ RedisCommand::Info(_)) => {
-                    // Result::Ok( ref info_command @ RedisCommand::Info(ref _ic)) => {
-                   handle_command(info_command.clone()).expect("BULK String expected")
-                    // RedisValue::BulkString(kv_info_string.to_owned())
+        RedisCommand::Debug(DebugSubcommand::SetActiveExpire(enabled)) => {
+            ACTIVE_EXPIRE.store(enabled, Ordering::Relaxed);
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Debug(DebugSubcommand::Sleep(duration, global)) => {
+            // a LOCAL sleep only blocks this client, so it must not hold up a
+            // global one either
+            drop(executing);
+            let _blocked = if global {
+                Some(EXECUTION.write().await)
+            } else {
+                None
+            };
+            sleep_unless_killed(duration, &conn.kill).await;
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Debug(DebugSubcommand::StringMatchLen(pattern, string)) => {
+            let matched = util::glob_match(pattern.as_bytes(), string.as_bytes(), false);
+            RedisValue::Integer(matched as i64)
+        }
+        RedisCommand::Debug(DebugSubcommand::ChangeReplId) => {
+            replication::change_replid();
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Debug(DebugSubcommand::ResetStats) => {
+            stats::reset();
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Debug(DebugSubcommand::Object(key)) => {
+            with_entry_mut(&key, |entry| match entry {
+                Some((value, _)) => RedisValue::SimpleString(debug_object(value)),
+                None => error::err("no such key"),
+            })
+        }
+        RedisCommand::Command(CommandSubcommand::List(filter)) => {
+            RedisValue::Array(
+                commands::COMMANDS
+                    .iter()
+                    .filter(|spec| match &filter {
+                        None => true,
+                        // there are no modules, so no command belongs to one
+                        Some(CommandFilter::Module) => false,
+                        Some(CommandFilter::AclCat(category)) => {
+                            commands::Category::from_name(category)
+                                == Some(spec.category)
+                        }
+                        Some(CommandFilter::Pattern(pattern)) => util::glob_match(
+                            pattern.as_bytes(),
+                            spec.name.as_bytes(),
+                            true,
+                        ),
+                    })
+                    .map(|spec| RedisValue::BulkString(spec.name.to_owned()))
+                    .collect(),
+            )
+        }
+        RedisCommand::Command(CommandSubcommand::Info(names)) => {
+            RedisValue::Array(
+                names
+                    .iter()
+                    .map(|name| match commands::lookup(&name.to_lowercase()) {
+                        Some(spec) => commands::info(spec),
+                        None => RedisValue::Null,
+                    })
+                    .collect(),
+            )
+        }
+        RedisCommand::Slowlog(SlowlogSubcommand::Get(count)) => {
+            slowlog::get(count)
+        }
+        RedisCommand::Slowlog(SlowlogSubcommand::Len) => {
+            RedisValue::Integer(slowlog::len() as i64)
+        }
+        RedisCommand::Replconf(options) => {
+            let replica = conn.replica.get_or_insert_with(Default::default);
+            for option in options {
+                match option {
+                    ReplconfOption::ListeningPort(port) => {
+                        replica.listening_port = Some(port)
+                    }
+                    ReplconfOption::Capa(capability) => {
+                        replica.capabilities.push(capability)
+                    }
                 }
+            }
+            replication::register_replica(conn.id, &conn.addr, replica.clone());
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Cluster(ClusterSubcommand::Info) => {
+            RedisValue::BulkString("cluster_enabled:0\r\n".to_owned())
+        }
+        RedisCommand::Cluster(
+            ClusterSubcommand::Slots | ClusterSubcommand::Shards,
+        ) => RedisValue::Array(vec![]),
+        RedisCommand::Cluster(ClusterSubcommand::MyId) => {
+            RedisValue::BulkString(server::run_id().to_owned())
+        }
+        RedisCommand::Help(command) => match commands::lookup(&command) {
+            Some(spec) => commands::help(spec),
+            None => error::err(format!("unknown command '{}'", command)),
+        },
+        RedisCommand::Monitor => {
+            conn.monitor = true;
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Quit => {
+            conn.quit = true;
+            RedisValue::SimpleString("OK".to_owned())
+        }
+        RedisCommand::Slowlog(SlowlogSubcommand::Reset) => {
+            slowlog::reset();
+            RedisValue::SimpleString("OK".to_owned())
+        }
 
-                _c => panic!("Cannot handle command."),
+    };
+    Ok(reply)
+}
+
+/// Forwards every command processed by any connection to this one until the
+/// client disconnects. Anything the client sends meanwhile is ignored.
+async fn stream_monitor<S>(handler: &mut resp::RespHandler<S>, conn: &ConnState) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut feed = monitor::subscribe();
+    loop {
+        tokio::select! {
+            line = feed.recv() => match line {
+                Result::Ok(line) => {
+                    handler
+                        .write_value(RedisValue::SimpleString(line), conn.protocol)
+                        .await?
+                }
+                Result::Err(RecvError::Lagged(_)) => {
+                    // same as Redis, rather than buffering without bound
+                    warning!(
+                        "Client id={} addr={} closed for overcoming of output buffer limits.",
+                        conn.id, conn.addr
+                    );
+                    break Ok(());
+                }
+                Result::Err(RecvError::Closed) => break Ok(()),
+            },
+            value = handler.read_value() => {
+                if value?.is_none() {
+                    break Ok(());
+                }
             }
-        } else {
-            break Ok(());
-        };
-        eprintln!("Sending value {:?}", response);
-        handler.write_value(response).await.unwrap();
+            _ = conn.kill.notified() => break Ok(()),
+        }
     }
 }
 
+/// Appends a write command to the AOF. Called while the change it records
+/// still holds the dataset lock, so the file sees writes in the order they
+/// were applied.
+fn propagate(command: RedisValue) {
+    if let Result::Err(e) = aof::feed(command) {
+        warning!("Writing to the append only file failed: {:?}", e);
+    }
+}
+
+/// The SET that recreates a string key, with any expiry as an absolute
+/// PXAT deadline so a replay does not restart the TTL.
+fn set_command(
+    key: RedisValue,
+    value: RedisValue,
+    timeout: &Option<(RedisValue, SystemTime)>,
+) -> RedisValue {
+    let mut command = vec![RedisValue::BulkString("SET".to_owned()), key, value];
+    if let Some((RedisValue::Integer(timeout), inserted_at)) = timeout {
+        command.push(RedisValue::BulkString("PXAT".to_owned()));
+        command.push(RedisValue::BulkString(
+            (unix_millis(*inserted_at) + timeout).to_string(),
+        ));
+    }
+    RedisValue::Array(command)
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+}
+
 fn handle_command(command: RedisCommand) -> Option<RedisValue> {
     match command {
-        RedisCommand::Set(key, value) => {
+        RedisCommand::Flush(mode) => {
             let mut hashmap = GLOBAL_HASHMAP.lock().unwrap();
-            hashmap.insert(key.clone(), (value.clone(), None));
-            eprintln!("\n\nhandle_command  {:?} -> {:?}\n", key, value);
-            eprintln!("\n\nhashmap  {:?}\n", hashmap);
-            None
+            let dataset = std::mem::take(&mut *hashmap);
+            propagate(RedisValue::Array(vec![RedisValue::BulkString(
+                "FLUSHALL".to_owned(),
+            )]));
+            drop(hashmap);
+            match mode {
+                FlushMode::Sync => drop(dataset),
+                FlushMode::Async => {
+                    tokio::task::spawn_blocking(move || drop(dataset));
+                }
+            }
+            Some(RedisValue::SimpleString("OK".to_owned()))
         }
-        RedisCommand::SetTimeout(key, value, timeout) => {
-            let mut hashmap = GLOBAL_HASHMAP.lock().unwrap();
-
-            hashmap.insert(
-                key.clone(),
-                (value.clone(), Some((timeout.clone(), SystemTime::now()))),
-            );
-            eprintln!("\n\nhandle_command SetTimeout  {:?} -> {:?}\n", key, value);
-            eprintln!("\n\nhashmap  {:?}\n", hashmap);
-            None
+        // replies with the value that was replaced, if any
+        RedisCommand::Set(key, value, options) => {
+            let previous = with_entry_mut(&key, |entry| {
+                // SET replaces a key of any type, but GET can't return one
+                if let (true, Err(wrong_type)) = (options.get, as_string(entry)) {
+                    return Some(wrong_type);
+                }
+                let previous = entry.take();
+                let now = SystemTime::now();
+                let timeout = match options.expiry {
+                    SetExpiry::Clear => None,
+                    SetExpiry::Px(timeout) => Some((timeout, now)),
+                    SetExpiry::PxAt(at) => Some((RedisValue::Integer(at - unix_millis(now)), now)),
+                    SetExpiry::KeepTtl => {
+                        previous.as_ref().and_then(|(_, timeout)| timeout.clone())
+                    }
+                };
+                propagate(set_command(key.clone(), value.clone(), &timeout));
+                // a deadline already in the past, as when replaying an old
+                // AOF, leaves no key behind
+                let expired = matches!(timeout, Some((RedisValue::Integer(ms), _)) if ms <= 0);
+                if !expired {
+                    *entry = Some((StoredValue::String(value.clone()), timeout));
+                }
+                match previous {
+                    Some((StoredValue::String(value), _)) => Some(value),
+                    _ => None,
+                }
+            });
+            debug!("SET {:?} -> {:?}", key, value);
+            previous
         }
-        RedisCommand::Get(key) => {
-            let hashmap = GLOBAL_HASHMAP.lock().unwrap();
-            if let Some(value_with_timeout) = hashmap.get(&key) {
-                match value_with_timeout {
-                    (value, None) => {
-                        eprintln!("\n\nGot value for key {:?} -> {:?}\n", key, value);
-
-                        Some(value.clone())
-                    }
-                    (value, Some((RedisValue::Integer(timeout), inserted_at))) => {
-                        let elapsed = inserted_at.elapsed().expect("no time elapsed?").as_millis();
-                        eprintln!("\nelapsed: {}", elapsed);
-                        if elapsed > *timeout as u128 {
-                            Some(RedisValue::BulkString("-1".to_owned())) // Return -1 if elapsed time is more than timeout
-                        } else {
-                            Some(value.clone()) // Return the original value if within timeout
+        RedisCommand::Get(key) => with_entry_mut(&key, |entry| match as_string(entry) {
+            Result::Ok(Some(value)) => {
+                debug!("GET {:?} -> {:?}", key, value);
+                Some(value.clone())
+            }
+            Result::Ok(None) => {
+                debug!("GET {:?} found no value", key);
+                None
+            }
+            Result::Err(wrong_type) => Some(wrong_type),
+        }),
+        RedisCommand::BitPos(key, bit, range) => {
+            with_entry_mut(&key, |entry| {
+                let position = match as_string(entry) {
+                    Result::Ok(Some(RedisValue::BulkString(s))) => bitpos(s.as_bytes(), bit, range),
+                    Result::Err(wrong_type) => return Some(wrong_type),
+                    // a missing key behaves like an endless run of zero bits
+                    _ if bit == 0 => 0,
+                    _ => -1,
+                };
+                Some(RedisValue::Integer(position))
+            })
+        }
+        RedisCommand::Lcs(key1, key2, options) => {
+            // missing keys compare as empty strings
+            let read = |key: &RedisValue| {
+                with_entry_mut(key, |entry| match as_string(entry) {
+                    Result::Ok(Some(RedisValue::BulkString(s))) => Result::Ok(s.clone()),
+                    Result::Ok(_) => Result::Ok(String::new()),
+                    Result::Err(wrong_type) => Result::Err(wrong_type),
+                })
+            };
+            match (read(&key1), read(&key2)) {
+                (Result::Ok(a), Result::Ok(b)) => {
+                    Some(lcs::reply(a.as_bytes(), b.as_bytes(), &options))
+                }
+                (Result::Err(wrong_type), _) | (_, Result::Err(wrong_type)) => Some(wrong_type),
+            }
+        }
+        RedisCommand::XAdd(key, id, fields) => with_entry_mut(&key, |entry| {
+            let created = entry.is_none();
+            if created {
+                *entry = Some((StoredValue::Stream(stream::Stream::default()), None));
+            }
+            let reply = match as_stream_mut(entry) {
+                Result::Ok(Some(stream)) => match stream.add(id, fields.clone()) {
+                    Result::Ok(id) => {
+                        // log the id that was assigned, so a replay does not
+                        // generate a different one
+                        let mut command = vec![
+                            RedisValue::BulkString("XADD".to_owned()),
+                            key.clone(),
+                            RedisValue::BulkString(id.to_string()),
+                        ];
+                        for (field, value) in fields {
+                            command.push(RedisValue::BulkString(field));
+                            command.push(RedisValue::BulkString(value));
                         }
+                        propagate(RedisValue::Array(command));
+                        stream::signal(&key);
+                        RedisValue::BulkString(id.to_string())
                     }
-                    _ => panic!("This timeout key should not be in global hashmap."),
+                    Result::Err(e) => error::err(e),
+                },
+                Result::Ok(None) => unreachable!("the stream was just created"),
+                Result::Err(wrong_type) => wrong_type,
+            };
+            // a rejected id must not leave an empty stream behind
+            if created && matches!(reply, RedisValue::Error(_)) {
+                *entry = None;
+            }
+            Some(reply)
+        }),
+        RedisCommand::XLen(key) => with_entry_mut(&key, |entry| match as_stream_mut(entry) {
+            Result::Ok(stream) => Some(RedisValue::Integer(
+                stream.map_or(0, |stream| stream.len()) as i64,
+            )),
+            Result::Err(wrong_type) => Some(wrong_type),
+        }),
+        // unlike XADD, neither creates a missing stream
+        RedisCommand::XDel(key, ids) => with_entry_mut(&key, |entry| match as_stream_mut(entry) {
+            Result::Ok(stream) => {
+                let deleted = stream.map_or(0, |stream| stream.delete(&ids));
+                if deleted > 0 {
+                    let mut command = vec![RedisValue::BulkString("XDEL".to_owned()), key.clone()];
+                    command.extend(ids.iter().map(|id| RedisValue::BulkString(id.to_string())));
+                    propagate(RedisValue::Array(command));
                 }
-            } else {
-                eprintln!("\n\nNo value found for key {:?}\n", key);
-                None
+                Some(RedisValue::Integer(deleted as i64))
             }
+            Result::Err(wrong_type) => Some(wrong_type),
+        }),
+        RedisCommand::XTrim(key, strategy) => {
+            with_entry_mut(&key, |entry| match as_stream_mut(entry) {
+                Result::Ok(stream) => {
+                    let trimmed = stream.map_or(0, |stream| stream.trim(strategy));
+                    if trimmed > 0 {
+                        let (kind, threshold) = match strategy {
+                            stream::TrimStrategy::MaxLen(max_len) => {
+                                ("MAXLEN", max_len.to_string())
+                            }
+                            stream::TrimStrategy::MinId(min_id) => ("MINID", min_id.to_string()),
+                        };
+                        propagate(RedisValue::Array(vec![
+                            RedisValue::BulkString("XTRIM".to_owned()),
+                            key.clone(),
+                            RedisValue::BulkString(kind.to_owned()),
+                            RedisValue::BulkString(threshold),
+                        ]));
+                    }
+                    Some(RedisValue::Integer(trimmed as i64))
+                }
+                Result::Err(wrong_type) => Some(wrong_type),
+            })
+        }
+        RedisCommand::XRange(key, start, end, count, rev) => {
+            with_entry_mut(&key, |entry| match as_stream_mut(entry) {
+                Result::Ok(Some(stream)) => {
+                    let entries = stream.range(start, end);
+                    let entries: Box<dyn Iterator<Item = _>> = if rev {
+                        Box::new(entries.rev())
+                    } else {
+                        Box::new(entries)
+                    };
+                    Some(RedisValue::Array(
+                        entries
+                            .take(count.unwrap_or(usize::MAX))
+                            .map(|(id, fields)| stream::entry_reply(id, fields))
+                            .collect(),
+                    ))
+                }
+                Result::Ok(None) => Some(RedisValue::Array(vec![])),
+                Result::Err(wrong_type) => Some(wrong_type),
+            })
+        }
+        RedisCommand::Memory(MemorySubcommand::Usage(key)) => {
+            with_entry_mut(&key, |entry| match entry {
+                Some(entry) => Some(RedisValue::Integer(memory::usage(&key, entry) as i64)),
+                None => Some(RedisValue::Null),
+            })
+        }
+        RedisCommand::Memory(subcommand) => {
+            let hashmap = GLOBAL_HASHMAP.lock().unwrap();
+            let stats =
+                memory::dataset_stats(hashmap.iter().filter(|(_, entry)| !is_expired(entry)));
+            if let MemorySubcommand::Doctor = subcommand {
+                return Some(RedisValue::BulkString(memory::doctor(&stats)));
+            }
+            let bytes_per_key = stats.total_bytes.checked_div(stats.keys).unwrap_or(0);
+            let stat = |name: &str, value: usize| {
+                (
+                    RedisValue::BulkString(name.to_owned()),
+                    RedisValue::Integer(value as i64),
+                )
+            };
+            Some(RedisValue::Map(vec![
+                stat("total.allocated", stats.total_bytes),
+                stat("keys.count", stats.keys),
+                stat("keys.bytes-per-key", bytes_per_key),
+                stat("expires.count", stats.expires),
+            ]))
         }
         // RedisCommand::Info(kv_info_string) => {
         //     let mut hashmap = GLOBAL_HASHMAP.lock().unwrap();
@@ -147,95 +1096,907 @@ fn handle_command(command: RedisCommand) -> Option<RedisValue> {
         //     kv_info.push_str(kv_info_string.to_owned().as_str());
         //     Some(RedisValue::BulkString(kv_info))
         // }
-        RedisCommand::Info(info_command) => {
-            match info_command {
-                RedisValue::BulkString(s) if s.to_lowercase() == "replication" => {
-                    Some(RedisValue::BulkString("role:master".to_owned()))
-                    // \r\nconnected_slaves:0\r\nmaster_replid:8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb\r\nmaster_repl_offset:0\r\n".to_string())
+        RedisCommand::Info(section) => {
+            let section = match section {
+                RedisValue::BulkString(s) => s.to_lowercase(),
+                _ => String::new(),
+            };
+            let info = match section.as_str() {
+                "replication" => replication::info(),
+                "server" => server::info(),
+                "stats" => stats::info(),
+                "all" | "everything" | "default" => [
+                    ("Server", server::info()),
+                    ("Replication", replication::info()),
+                    ("Stats", stats::info()),
+                ]
+                .into_iter()
+                .map(|(name, info)| format!("# {}\r\n{}", name, info))
+                .collect::<Vec<_>>()
+                .join("\r\n\r\n"),
+                // like Redis, a section it does not know is just empty
+                _ => String::new(),
+            };
+            Some(RedisValue::BulkString(info))
+        }
+        _ => Some(error::err("command does not work on the keyspace")),
+    }
+}
+
+/// The DEBUG OBJECT line describing how a value is stored.
+fn debug_object(value: &StoredValue) -> String {
+    let (encoding, length) = match value {
+        StoredValue::String(RedisValue::BulkString(s)) if s.parse::<i64>().is_ok() => {
+            ("int", s.len())
+        }
+        // same cut-off as Redis between embedded and separately allocated strings
+        StoredValue::String(RedisValue::BulkString(s)) if s.len() <= 44 => ("embstr", s.len()),
+        StoredValue::String(RedisValue::BulkString(s)) => ("raw", s.len()),
+        StoredValue::String(_) => ("raw", 0),
+        StoredValue::Stream(stream) => ("stream", stream.len()),
+    };
+    let mut line = format!(
+        "Value at:{:p} refcount:1 encoding:{} serializedlength:{} lru:0 lru_seconds_idle:0",
+        value, encoding, length
+    );
+    // there is no radix tree here to count, so the radix tree figures are
+    // estimates, reported under Redis' field names for clients that parse them
+    if let StoredValue::Stream(stream) = value {
+        line.push_str(&format!(
+            " radix-tree-keys:{} radix-tree-nodes:{} last-generated-id:{}",
+            stream.estimated_radix_tree_keys(),
+            stream.estimated_radix_tree_nodes(),
+            stream.last_id
+        ));
+    }
+    line
+}
+
+fn hello_reply(protocol: Protocol) -> RedisValue {
+    let field = |name: &str| RedisValue::BulkString(name.to_owned());
+    RedisValue::Map(vec![
+        (field("server"), field("redis")),
+        (field("version"), field(REDIS_VERSION)),
+        (field("proto"), RedisValue::Integer(protocol.version())),
+        (field("mode"), field("standalone")),
+        (field("role"), field("master")),
+        (field("modules"), RedisValue::Array(vec![])),
+    ])
+}
+
+/// Runs `f` on the entry for `key` under a single lock acquisition, so a
+/// read, update and delete of one key cannot interleave with another command.
+/// An expired entry is passed as `None`. Whatever `f` leaves in the entry is
+/// stored back, and leaving `None` deletes the key.
+fn with_entry_mut<T>(key: &RedisValue, f: impl FnOnce(&mut Option<StoredEntry>) -> T) -> T {
+    let mut hashmap = GLOBAL_HASHMAP.lock().unwrap();
+    let (mut entry, stale) = match hashmap.remove(key) {
+        // with active expiry off the key lingers, but is still gone for clients
+        Some(expired) if is_expired(&expired) => (
+            None,
+            (!ACTIVE_EXPIRE.load(Ordering::Relaxed)).then_some(expired),
+        ),
+        live => (live, None),
+    };
+    let result = f(&mut entry);
+    if let Some(entry) = entry.or(stale) {
+        hashmap.insert(key.clone(), entry);
+    }
+    result
+}
+
+/// The XREAD reply: for every stream with entries past the given id, the
+/// key and up to `count` of those entries. `None` when there are none.
+fn xread(streams: &[(RedisValue, stream::StreamId)], count: Option<usize>) -> Option<RedisValue> {
+    let mut replies = vec![];
+    for (key, after) in streams {
+        let Some(start) = after.next() else {
+            continue;
+        };
+        let entries = with_entry_mut(key, |entry| match as_stream_mut(entry) {
+            Result::Ok(Some(stream)) => Result::Ok(
+                stream
+                    .range(start, stream::StreamId::MAX)
+                    .take(count.unwrap_or(usize::MAX))
+                    .map(|(id, fields)| stream::entry_reply(id, fields))
+                    .collect::<Vec<_>>(),
+            ),
+            Result::Ok(None) => Result::Ok(vec![]),
+            Result::Err(wrong_type) => Result::Err(wrong_type),
+        });
+        match entries {
+            Result::Ok(entries) if entries.is_empty() => {}
+            Result::Ok(entries) => replies.push(RedisValue::Array(vec![
+                key.clone(),
+                RedisValue::Array(entries),
+            ])),
+            Result::Err(wrong_type) => return Some(wrong_type),
+        }
+    }
+    (!replies.is_empty()).then_some(RedisValue::Array(replies))
+}
+
+/// The string held by `entry`, or `None` for a missing key. A key of any
+/// other type gives the WRONGTYPE reply to send back instead.
+fn as_string(entry: &Option<StoredEntry>) -> Result<Option<&RedisValue>, RedisValue> {
+    match entry {
+        Some((StoredValue::String(value), _)) => Result::Ok(Some(value)),
+        Some(_) => Result::Err(error::wrong_type()),
+        None => Result::Ok(None),
+    }
+}
+
+/// Like `as_string`, for commands working on a stream.
+fn as_stream_mut(
+    entry: &mut Option<StoredEntry>,
+) -> Result<Option<&mut stream::Stream>, RedisValue> {
+    match entry {
+        Some((StoredValue::Stream(stream), _)) => Result::Ok(Some(stream)),
+        Some(_) => Result::Err(error::wrong_type()),
+        None => Result::Ok(None),
+    }
+}
+
+fn is_expired((_, timeout): &StoredEntry) -> bool {
+    match timeout {
+        Some((RedisValue::Integer(timeout), inserted_at)) => {
+            inserted_at.elapsed().expect("no time elapsed?").as_millis() > *timeout as u128
+        }
+        _ => false,
+    }
+}
+
+/// Position of the first bit equal to `bit` in `bytes`, or -1 if there is none.
+fn bitpos(bytes: &[u8], bit: u8, range: Option<BitRange>) -> i64 {
+    let byte_len = bytes.len() as i64;
+    let (start, end, in_bits) = match &range {
+        Some(r) => {
+            let len = if r.in_bits { byte_len * 8 } else { byte_len };
+            (r.start, r.end.unwrap_or(len - 1), r.in_bits)
+        }
+        None => (0, byte_len - 1, false),
+    };
+
+    // same normalisation as GETRANGE: negative offsets count from the end
+    let len = if in_bits { byte_len * 8 } else { byte_len };
+    let mut start = if start < 0 { start + len } else { start };
+    let mut end = if end < 0 { end + len } else { end };
+    start = start.max(0);
+    end = end.max(0);
+    if end >= len {
+        end = len - 1;
+    }
+    if start > end {
+        return -1;
+    }
+
+    let (first_bit, last_bit) = if in_bits {
+        (start, end)
+    } else {
+        (start * 8, end * 8 + 7)
+    };
+    for pos in first_bit..=last_bit {
+        let byte = bytes[(pos / 8) as usize];
+        if (byte >> (7 - pos % 8)) & 1 == bit {
+            return pos;
+        }
+    }
+
+    // looking for a clear bit without an explicit end: the value is treated
+    // as padded with zeros, so the answer is the bit just past the range
+    let end_given = matches!(range, Some(BitRange { end: Some(_), .. }));
+    if bit == 0 && !end_given {
+        last_bit + 1
+    } else {
+        -1
+    }
+}
+
+/// The minimal set of SET and XADD commands that rebuilds the current dataset.
+fn dataset_as_commands(hashmap: &HashMap<RedisValue, StoredEntry>) -> Vec<RedisValue> {
+    let mut commands = vec![];
+    for (key, entry) in hashmap.iter() {
+        if is_expired(entry) {
+            // nothing to rebuild
+            continue;
+        }
+        let (value, timeout) = entry;
+        let value = match value {
+            StoredValue::String(value) => value,
+            StoredValue::Stream(stream) => {
+                for (id, fields) in &stream.entries {
+                    let mut command = vec![
+                        RedisValue::BulkString("XADD".to_owned()),
+                        key.clone(),
+                        RedisValue::BulkString(id.to_string()),
+                    ];
+                    for (field, value) in fields {
+                        command.push(RedisValue::BulkString(field.clone()));
+                        command.push(RedisValue::BulkString(value.clone()));
+                    }
+                    commands.push(RedisValue::Array(command));
                 }
-                _ => {
-                    panic!(
-                        "info command is not replication. it is: {:?} ",
-                        info_command
-                    )
+                // XDEL and XTRIM may have removed the newest entry, or all of
+                // them; adding and deleting a placeholder restores the last
+                // id, and keeps an emptied stream
+                if stream.entries.last_key_value().map(|(id, _)| *id) != Some(stream.last_id) {
+                    let id = RedisValue::BulkString(stream.last_id.to_string());
+                    commands.push(RedisValue::Array(vec![
+                        RedisValue::BulkString("XADD".to_owned()),
+                        key.clone(),
+                        id.clone(),
+                        RedisValue::BulkString(String::new()),
+                        RedisValue::BulkString(String::new()),
+                    ]));
+                    commands.push(RedisValue::Array(vec![
+                        RedisValue::BulkString("XDEL".to_owned()),
+                        key.clone(),
+                        id,
+                    ]));
                 }
+                continue;
             }
-        }
-        _ => panic!("Can handle only Set command yet."),
+        };
+        commands.push(set_command(key.clone(), value.clone(), timeout));
     }
+    commands
 }
 
 fn extract_command(value: RedisValue) -> Result<(String, Vec<RedisValue>)> {
     match value {
-        RedisValue::Array(a) => Ok((
-            unpack_bulk_str(a.first().unwrap().clone())?,
-            a.into_iter().skip(1).collect(),
-        )),
+        RedisValue::Array(a) => {
+            let mut a = a.into_iter();
+            let name = a
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Protocol error: empty command"))?;
+            Ok((unpack_bulk_str(name)?, a.collect()))
+        }
         _ => Err(anyhow::anyhow!("Unexpected command format")),
     }
 }
 
 fn to_command((command, args): (String, Vec<RedisValue>)) -> Result<RedisCommand> {
-    match command.to_lowercase().as_str() {
+    let command = command.to_lowercase();
+    if !commands::arity_matches(&command, args.len() + 1) {
+        return Err(anyhow::anyhow!(
+            "wrong number of arguments for '{}' command",
+            command
+        ));
+    }
+    match command.as_str() {
         "echo" => Ok(RedisCommand::Echo(args.first().unwrap().clone())),
+        "flushall" | "flushdb" => {
+            let mode = match args.first() {
+                None => FlushMode::default(),
+                Some(mode) if args.len() == 1 => {
+                    match unpack_bulk_str(mode.clone())?.to_lowercase().as_str() {
+                        "sync" => FlushMode::Sync,
+                        "async" => FlushMode::Async,
+                        _ => return Err(anyhow::anyhow!("syntax error")),
+                    }
+                }
+                Some(_) => return Err(anyhow::anyhow!("syntax error")),
+            };
+            Ok(RedisCommand::Flush(mode))
+        }
         "set" => {
             if args.len() < 2 {
                 return Err(anyhow::anyhow!("Set command requires a key and a value"));
             }
 
-            if args.len() == 4 {
-                let key = args.get(0).unwrap().clone();
-                let value = args.get(1).unwrap().clone();
-                if let RedisValue::BulkString(px_command) = args.get(2).unwrap().clone() {
-                    let timeout = match px_command.to_lowercase().as_str() {
-                        "px" => {
-                            if let RedisValue::BulkString(num_as_str) = args.get(3).unwrap().clone()
-                            {
-                                dbg!(&num_as_str);
-                                RedisValue::Integer(
-                                    parse_int_with_sign(num_as_str.as_bytes()).unwrap(),
-                                )
-                            } else {
-                                RedisValue::Integer(1000)
-                            }
+            let key = args.first().unwrap().clone();
+            let value = args.get(1).unwrap().clone();
+            let mut set_options = SetOptions::default();
+            let mut options = args.iter().skip(2);
+            while let Some(option) = options.next() {
+                // PX, PXAT and KEEPTTL are mutually exclusive
+                let expiry_unset = matches!(set_options.expiry, SetExpiry::Clear);
+                match unpack_bulk_str(option.clone())?.to_lowercase().as_str() {
+                    "px" if expiry_unset => {
+                        let num_as_str = match options.next() {
+                            Some(num) => unpack_bulk_str(num.clone())?,
+                            None => return Err(anyhow::anyhow!("syntax error")),
+                        };
+                        let ms = parse_int_with_sign(num_as_str.as_bytes())?;
+                        if ms <= 0 {
+                            return Err(anyhow::anyhow!("invalid expire time in 'set' command"));
                         }
-                        _ => {
-                            return Err(anyhow::anyhow!(
-                                "cannot parse anything other than px command in set"
-                            ))
+                        set_options.expiry = SetExpiry::Px(RedisValue::Integer(ms));
+                    }
+                    "pxat" if expiry_unset => {
+                        let num_as_str = match options.next() {
+                            Some(num) => unpack_bulk_str(num.clone())?,
+                            None => return Err(anyhow::anyhow!("syntax error")),
+                        };
+                        let at = parse_int_with_sign(num_as_str.as_bytes())?;
+                        if at <= 0 {
+                            return Err(anyhow::anyhow!("invalid expire time in 'set' command"));
                         }
-                    };
-
-                    Ok(RedisCommand::SetTimeout(key, value, timeout))
-                } else {
-                    panic!("px command expected but not found");
+                        set_options.expiry = SetExpiry::PxAt(at);
+                    }
+                    "keepttl" if expiry_unset => set_options.expiry = SetExpiry::KeepTtl,
+                    "get" if !set_options.get => set_options.get = true,
+                    _ => return Err(anyhow::anyhow!("syntax error")),
                 }
-            } else {
-                let key = args.get(0).unwrap().clone();
-                let value = args.get(1).unwrap().clone();
-                Ok(RedisCommand::Set(key, value))
             }
+            Ok(RedisCommand::Set(key, value, set_options))
         }
         "get" => {
-            if args.len() < 1 {
+            if args.is_empty() {
                 return Err(anyhow::anyhow!("get command requires a key"));
             }
-            let key = args.get(0).unwrap().clone();
+            let key = args.first().unwrap().clone();
             Ok(RedisCommand::Get(key))
         }
         // RedisValue::SimpleString("PONG".to_string()),
         "ping" => Ok(RedisCommand::Ping),
-        "info" => {
-            if args.len() < 1 {
-                // todo in future, return all the 'info sections'
-                return Err(anyhow::anyhow!("info command assumes an argument"));
+        "bgrewriteaof" => Ok(RedisCommand::BgRewriteAof),
+        "waitaof" => {
+            if args.len() != 3 {
+                return Err(anyhow::anyhow!(
+                    "waitaof requires numlocal, numreplicas and timeout"
+                ));
+            }
+            let mut numbers = vec![];
+            for arg in args {
+                let number = parse_int_with_sign(unpack_bulk_str(arg)?.as_bytes())?;
+                if number < 0 {
+                    return Err(anyhow::anyhow!("value is out of range, must be positive"));
+                }
+                numbers.push(number);
+            }
+            Ok(RedisCommand::WaitAof(numbers[0], numbers[1], numbers[2]))
+        }
+        "auth" => match args.len() {
+            1 => Ok(RedisCommand::Auth(
+                None,
+                unpack_bulk_str(args.first().unwrap().clone())?,
+            )),
+            2 => Ok(RedisCommand::Auth(
+                Some(unpack_bulk_str(args.first().unwrap().clone())?),
+                unpack_bulk_str(args.get(1).unwrap().clone())?,
+            )),
+            _ => Err(anyhow::anyhow!(
+                "auth command requires a password and an optional username"
+            )),
+        },
+        "debug" => {
+            let subcommand = match args.first() {
+                Some(subcommand) => unpack_bulk_str(subcommand.clone())?,
+                None => return Err(anyhow::anyhow!("debug command requires a subcommand")),
+            };
+            match subcommand.to_lowercase().as_str() {
+                "set-active-expire" => {
+                    let enabled = match args.get(1) {
+                        Some(flag) => match unpack_bulk_str(flag.clone())?.as_str() {
+                            "0" => false,
+                            "1" => true,
+                            _ => return Err(anyhow::anyhow!("set-active-expire takes 0 or 1")),
+                        },
+                        None => return Err(anyhow::anyhow!("set-active-expire takes 0 or 1")),
+                    };
+                    Ok(RedisCommand::Debug(DebugSubcommand::SetActiveExpire(
+                        enabled,
+                    )))
+                }
+                "change-repl-id" => Ok(RedisCommand::Debug(DebugSubcommand::ChangeReplId)),
+                "reset-stats" => Ok(RedisCommand::Debug(DebugSubcommand::ResetStats)),
+                "object" => match args.get(1) {
+                    Some(key) if args.len() == 2 => {
+                        Ok(RedisCommand::Debug(DebugSubcommand::Object(key.clone())))
+                    }
+                    _ => Err(anyhow::anyhow!("debug object requires a key")),
+                },
+                "stringmatch-len" => {
+                    if args.len() != 3 {
+                        return Err(anyhow::anyhow!(
+                            "debug stringmatch-len requires a pattern and a string"
+                        ));
+                    }
+                    let pattern = unpack_bulk_str(args.get(1).unwrap().clone())?;
+                    let string = unpack_bulk_str(args.get(2).unwrap().clone())?;
+                    Ok(RedisCommand::Debug(DebugSubcommand::StringMatchLen(
+                        pattern, string,
+                    )))
+                }
+                "sleep" => {
+                    let seconds = match args.get(1) {
+                        Some(seconds) => unpack_bulk_str(seconds.clone())?,
+                        None => return Err(anyhow::anyhow!("debug sleep requires a duration")),
+                    };
+                    let duration = match seconds.parse::<f64>() {
+                        Result::Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => {
+                            Duration::from_secs_f64(seconds)
+                        }
+                        _ => return Err(anyhow::anyhow!("value is not a valid float")),
+                    };
+                    let global = match args.get(2) {
+                        None => true,
+                        Some(scope) if args.len() == 3 => {
+                            match unpack_bulk_str(scope.clone())?.to_lowercase().as_str() {
+                                "local" => false,
+                                _ => return Err(anyhow::anyhow!("syntax error")),
+                            }
+                        }
+                        Some(_) => return Err(anyhow::anyhow!("syntax error")),
+                    };
+                    Ok(RedisCommand::Debug(DebugSubcommand::Sleep(
+                        duration, global,
+                    )))
+                }
+                "help" => Ok(RedisCommand::Help("debug".to_owned())),
+                s => Err(anyhow::anyhow!("Unknown debug subcommand: {:?}", s)),
+            }
+        }
+        "hello" => {
+            let version = match args.first() {
+                Some(version) => Some(parse_int_with_sign(
+                    unpack_bulk_str(version.clone())?.as_bytes(),
+                )?),
+                None => None,
+            };
+            let mut auth = None;
+            let mut options = args.iter().skip(1);
+            while let Some(option) = options.next() {
+                match unpack_bulk_str(option.clone())?.to_lowercase().as_str() {
+                    "auth" => match (options.next(), options.next()) {
+                        (Some(username), Some(password)) => {
+                            auth = Some((
+                                unpack_bulk_str(username.clone())?,
+                                unpack_bulk_str(password.clone())?,
+                            ))
+                        }
+                        _ => return Err(anyhow::anyhow!("syntax error in HELLO option 'auth'")),
+                    },
+                    o => return Err(anyhow::anyhow!("syntax error in HELLO option '{}'", o)),
+                }
+            }
+            Ok(RedisCommand::Hello(version, auth))
+        }
+        "monitor" => Ok(RedisCommand::Monitor),
+        "quit" => Ok(RedisCommand::Quit),
+        "replconf" => {
+            if args.is_empty() || args.len() % 2 != 0 {
+                return Err(anyhow::anyhow!("syntax error"));
+            }
+            let mut options = vec![];
+            for pair in args.chunks(2) {
+                let option = unpack_bulk_str(pair[0].clone())?;
+                let value = unpack_bulk_str(pair[1].clone())?;
+                options.push(match option.to_lowercase().as_str() {
+                    "listening-port" => ReplconfOption::ListeningPort(value.parse()?),
+                    "capa" => ReplconfOption::Capa(value),
+                    _ => return Err(anyhow::anyhow!("Unrecognized REPLCONF option: {}", option)),
+                });
+            }
+            Ok(RedisCommand::Replconf(options))
+        }
+        "cluster" => {
+            let subcommand = match args.first() {
+                Some(subcommand) => unpack_bulk_str(subcommand.clone())?,
+                None => return Err(anyhow::anyhow!("cluster command requires a subcommand")),
+            };
+            match subcommand.to_lowercase().as_str() {
+                "info" => Ok(RedisCommand::Cluster(ClusterSubcommand::Info)),
+                "slots" => Ok(RedisCommand::Cluster(ClusterSubcommand::Slots)),
+                "shards" => Ok(RedisCommand::Cluster(ClusterSubcommand::Shards)),
+                "myid" => Ok(RedisCommand::Cluster(ClusterSubcommand::MyId)),
+                "help" => Ok(RedisCommand::Help("cluster".to_owned())),
+                s => Err(anyhow::anyhow!("Unknown cluster subcommand: {:?}", s)),
+            }
+        }
+        "slowlog" => {
+            let subcommand = match args.first() {
+                Some(subcommand) => unpack_bulk_str(subcommand.clone())?,
+                None => return Err(anyhow::anyhow!("slowlog command requires a subcommand")),
+            };
+            match subcommand.to_lowercase().as_str() {
+                "get" => {
+                    let count = match args.get(1) {
+                        Some(count) => Some(parse_int_with_sign(
+                            unpack_bulk_str(count.clone())?.as_bytes(),
+                        )?),
+                        None => None,
+                    };
+                    Ok(RedisCommand::Slowlog(SlowlogSubcommand::Get(count)))
+                }
+                "len" => Ok(RedisCommand::Slowlog(SlowlogSubcommand::Len)),
+                "reset" => Ok(RedisCommand::Slowlog(SlowlogSubcommand::Reset)),
+                "help" => Ok(RedisCommand::Help("slowlog".to_owned())),
+                s => Err(anyhow::anyhow!("Unknown slowlog subcommand: {:?}", s)),
+            }
+        }
+        "command" => {
+            let subcommand = match args.first() {
+                Some(subcommand) => unpack_bulk_str(subcommand.clone())?,
+                None => return Err(anyhow::anyhow!("command requires a subcommand")),
+            };
+            match subcommand.to_lowercase().as_str() {
+                "info" => {
+                    let names = args
+                        .into_iter()
+                        .skip(1)
+                        .map(unpack_bulk_str)
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok(RedisCommand::Command(CommandSubcommand::Info(names)))
+                }
+                "list" => {
+                    let filter = match &args[1..] {
+                        [] => None,
+                        [filterby, kind, value] => {
+                            if unpack_bulk_str(filterby.clone())?.to_lowercase() != "filterby" {
+                                return Err(anyhow::anyhow!("syntax error"));
+                            }
+                            let value = unpack_bulk_str(value.clone())?;
+                            Some(
+                                match unpack_bulk_str(kind.clone())?.to_lowercase().as_str() {
+                                    "module" => CommandFilter::Module,
+                                    "aclcat" => CommandFilter::AclCat(value),
+                                    "pattern" => CommandFilter::Pattern(value),
+                                    _ => return Err(anyhow::anyhow!("syntax error")),
+                                },
+                            )
+                        }
+                        _ => return Err(anyhow::anyhow!("syntax error")),
+                    };
+                    Ok(RedisCommand::Command(CommandSubcommand::List(filter)))
+                }
+                "help" => Ok(RedisCommand::Help("command".to_owned())),
+                s => Err(anyhow::anyhow!("Unknown command subcommand: {:?}", s)),
+            }
+        }
+        "client" => {
+            let subcommand = match args.first() {
+                Some(subcommand) => unpack_bulk_str(subcommand.clone())?,
+                None => return Err(anyhow::anyhow!("client command requires a subcommand")),
+            };
+            match subcommand.to_lowercase().as_str() {
+                "id" => Ok(RedisCommand::Client(ClientSubcommand::Id)),
+                "kill" if args.len() == 2 => {
+                    let addr = unpack_bulk_str(args.get(1).unwrap().clone())?;
+                    let filter = clients::KillFilter {
+                        addr: Some(addr),
+                        ..Default::default()
+                    };
+                    Ok(RedisCommand::Client(ClientSubcommand::Kill(
+                        filter, true, false,
+                    )))
+                }
+                "kill" => {
+                    if args.len() < 3 || args.len() % 2 == 0 {
+                        return Err(anyhow::anyhow!("syntax error"));
+                    }
+                    let mut filter = clients::KillFilter::default();
+                    let mut skip_me = true;
+                    for pair in args[1..].chunks(2) {
+                        let option = unpack_bulk_str(pair[0].clone())?;
+                        let value = unpack_bulk_str(pair[1].clone())?;
+                        match option.to_lowercase().as_str() {
+                            "id" => match value.parse::<u64>() {
+                                Result::Ok(id) if id > 0 => filter.id = Some(id),
+                                _ => {
+                                    return Err(anyhow::anyhow!(
+                                        "client-id should be greater than 0"
+                                    ))
+                                }
+                            },
+                            "addr" => filter.addr = Some(value),
+                            "skipme" => match value.to_lowercase().as_str() {
+                                "yes" => skip_me = true,
+                                "no" => skip_me = false,
+                                _ => return Err(anyhow::anyhow!("syntax error")),
+                            },
+                            _ => return Err(anyhow::anyhow!("syntax error")),
+                        }
+                    }
+                    Ok(RedisCommand::Client(ClientSubcommand::Kill(
+                        filter, false, skip_me,
+                    )))
+                }
+                "pause" => {
+                    let timeout = match args.get(1) {
+                        Some(timeout) => unpack_bulk_str(timeout.clone())?,
+                        None => return Err(anyhow::anyhow!("client pause requires a timeout")),
+                    };
+                    let timeout = match timeout.parse::<u64>() {
+                        Result::Ok(ms) => Duration::from_millis(ms),
+                        Result::Err(_) => {
+                            return Err(anyhow::anyhow!(
+                                "timeout is not an integer or out of range"
+                            ))
+                        }
+                    };
+                    let mode = match args.get(2) {
+                        None => clients::PauseMode::All,
+                        Some(mode) if args.len() == 3 => {
+                            match unpack_bulk_str(mode.clone())?.to_lowercase().as_str() {
+                                "write" => clients::PauseMode::Write,
+                                "all" => clients::PauseMode::All,
+                                _ => return Err(anyhow::anyhow!("syntax error")),
+                            }
+                        }
+                        Some(_) => return Err(anyhow::anyhow!("syntax error")),
+                    };
+                    Ok(RedisCommand::Client(ClientSubcommand::Pause(timeout, mode)))
+                }
+                "help" => Ok(RedisCommand::Help("client".to_owned())),
+                s => Err(anyhow::anyhow!("Unknown client subcommand: {:?}", s)),
+            }
+        }
+        "acl" => {
+            let subcommand = match args.first() {
+                Some(subcommand) => unpack_bulk_str(subcommand.clone())?,
+                None => return Err(anyhow::anyhow!("acl command requires a subcommand")),
+            };
+            match subcommand.to_lowercase().as_str() {
+                "whoami" => Ok(RedisCommand::Acl(AclSubcommand::WhoAmI)),
+                "setuser" => {
+                    if args.len() < 2 {
+                        return Err(anyhow::anyhow!("acl setuser requires a username"));
+                    }
+                    let username = unpack_bulk_str(args.get(1).unwrap().clone())?;
+                    let rules = args
+                        .into_iter()
+                        .skip(2)
+                        .map(unpack_bulk_str)
+                        .collect::<Result<Vec<_>>>()?;
+                    Ok(RedisCommand::Acl(AclSubcommand::SetUser(username, rules)))
+                }
+                "help" => Ok(RedisCommand::Help("acl".to_owned())),
+                s => Err(anyhow::anyhow!("Unknown acl subcommand: {:?}", s)),
+            }
+        }
+        "memory" => {
+            let subcommand = match args.first() {
+                Some(subcommand) => unpack_bulk_str(subcommand.clone())?,
+                None => return Err(anyhow::anyhow!("memory command requires a subcommand")),
+            };
+            match subcommand.to_lowercase().as_str() {
+                "usage" => {
+                    // SAMPLES only matters for nested values, so it is accepted and ignored
+                    let valid_samples = match args.get(2) {
+                        None => args.len() == 2,
+                        Some(option) => {
+                            unpack_bulk_str(option.clone())?.to_lowercase() == "samples"
+                                && args.len() == 4
+                        }
+                    };
+                    if !valid_samples {
+                        return Err(anyhow::anyhow!(
+                            "memory usage requires a key and an optional SAMPLES count"
+                        ));
+                    }
+                    let key = args.get(1).unwrap().clone();
+                    Ok(RedisCommand::Memory(MemorySubcommand::Usage(key)))
+                }
+                "doctor" => Ok(RedisCommand::Memory(MemorySubcommand::Doctor)),
+                "stats" => Ok(RedisCommand::Memory(MemorySubcommand::Stats)),
+                "help" => Ok(RedisCommand::Help("memory".to_owned())),
+                s => Err(anyhow::anyhow!("Unknown memory subcommand: {:?}", s)),
+            }
+        }
+        "bitpos" => {
+            if args.len() < 2 || args.len() > 5 {
+                return Err(anyhow::anyhow!(
+                    "bitpos command requires a key, a bit and an optional range"
+                ));
+            }
+            let key = args.first().unwrap().clone();
+            let bit = match unpack_bulk_str(args.get(1).unwrap().clone())?.as_str() {
+                "0" => 0,
+                "1" => 1,
+                _ => return Err(anyhow::anyhow!("The bit argument must be 1 or 0.")),
+            };
+            let range = if let Some(start) = args.get(2) {
+                let start = parse_int_with_sign(unpack_bulk_str(start.clone())?.as_bytes())?;
+                let end = match args.get(3) {
+                    Some(end) => Some(parse_int_with_sign(
+                        unpack_bulk_str(end.clone())?.as_bytes(),
+                    )?),
+                    None => None,
+                };
+                let in_bits = match args.get(4) {
+                    Some(unit) => match unpack_bulk_str(unit.clone())?.to_lowercase().as_str() {
+                        "byte" => false,
+                        "bit" => true,
+                        _ => return Err(anyhow::anyhow!("bitpos unit must be BYTE or BIT")),
+                    },
+                    None => false,
+                };
+                Some(BitRange {
+                    start,
+                    end,
+                    in_bits,
+                })
             } else {
-                // return only the replication info section
-                Ok(RedisCommand::Info(args.first().unwrap().clone()))
+                None
+            };
+            Ok(RedisCommand::BitPos(key, bit, range))
+        }
+        "lcs" => {
+            if args.len() < 2 {
+                return Err(anyhow::anyhow!("LCS command requires two keys"));
+            }
+            let key1 = args.first().unwrap().clone();
+            let key2 = args.get(1).unwrap().clone();
+            let mut options = lcs::LcsOptions::default();
+            let mut rest = args.iter().skip(2);
+            while let Some(option) = rest.next() {
+                match unpack_bulk_str(option.clone())?.to_lowercase().as_str() {
+                    "len" => options.len = true,
+                    "idx" => options.idx = true,
+                    "withmatchlen" => options.with_match_len = true,
+                    "minmatchlen" => {
+                        let len = match rest.next() {
+                            Some(len) => {
+                                parse_int_with_sign(unpack_bulk_str(len.clone())?.as_bytes())?
+                            }
+                            None => return Err(anyhow::anyhow!("syntax error")),
+                        };
+                        // a negative minimum is the same as no minimum
+                        options.min_match_len = len.max(0) as usize;
+                    }
+                    _ => return Err(anyhow::anyhow!("syntax error")),
+                }
+            }
+            if options.len && options.idx {
+                return Err(anyhow::anyhow!(
+                    "If you want both the length and indexes, please just use IDX."
+                ));
+            }
+            Ok(RedisCommand::Lcs(key1, key2, options))
+        }
+        "xadd" => {
+            if args.len() < 4 || args.len() % 2 != 0 {
+                return Err(anyhow::anyhow!(
+                    "wrong number of arguments for 'xadd' command"
+                ));
+            }
+            let key = args.first().unwrap().clone();
+            let id = stream::XAddId::parse(&unpack_bulk_str(args.get(1).unwrap().clone())?)?;
+            let mut fields = vec![];
+            for pair in args[2..].chunks(2) {
+                fields.push((
+                    unpack_bulk_str(pair[0].clone())?,
+                    unpack_bulk_str(pair[1].clone())?,
+                ));
+            }
+            Ok(RedisCommand::XAdd(key, id, fields))
+        }
+        "xrange" | "xrevrange" => {
+            let rev = command.eq_ignore_ascii_case("xrevrange");
+            if args.len() != 3 && args.len() != 5 {
+                return Err(anyhow::anyhow!(
+                    "wrong number of arguments for '{}' command",
+                    command.to_lowercase()
+                ));
+            }
+            let key = args.first().unwrap().clone();
+            let first = unpack_bulk_str(args.get(1).unwrap().clone())?;
+            let second = unpack_bulk_str(args.get(2).unwrap().clone())?;
+            // XREVRANGE takes its interval end first
+            let (start, end) = if rev {
+                (stream::range_start(&second)?, stream::range_end(&first)?)
+            } else {
+                (stream::range_start(&first)?, stream::range_end(&second)?)
+            };
+            let count = match args.get(3) {
+                Some(option) => {
+                    if unpack_bulk_str(option.clone())?.to_lowercase() != "count" {
+                        return Err(anyhow::anyhow!("syntax error"));
+                    }
+                    let count = parse_int_with_sign(
+                        unpack_bulk_str(args.get(4).unwrap().clone())?.as_bytes(),
+                    )?;
+                    Some(count.max(0) as usize)
+                }
+                None => None,
+            };
+            Ok(RedisCommand::XRange(key, start, end, count, rev))
+        }
+        "xread" => {
+            let mut count = None;
+            let mut block = None;
+            let mut rest = args.into_iter();
+            loop {
+                let option = match rest.next() {
+                    Some(option) => unpack_bulk_str(option)?,
+                    None => return Err(anyhow::anyhow!("syntax error")),
+                };
+                let mut number = || -> Result<i64> {
+                    match rest.next() {
+                        Some(n) => parse_int_with_sign(unpack_bulk_str(n)?.as_bytes()),
+                        None => Err(anyhow::anyhow!("syntax error")),
+                    }
+                };
+                match option.to_lowercase().as_str() {
+                    // like Redis, a COUNT of 0 or less means no limit
+                    "count" => count = Some(number()?).filter(|n| *n > 0).map(|n| n as usize),
+                    "block" => {
+                        let ms = number()?;
+                        if ms < 0 {
+                            return Err(anyhow::anyhow!("timeout is negative"));
+                        }
+                        block = Some(ms as u64);
+                    }
+                    "streams" => break,
+                    _ => return Err(anyhow::anyhow!("syntax error")),
+                }
+            }
+            let rest: Vec<_> = rest.collect();
+            if rest.is_empty() || rest.len() % 2 != 0 {
+                return Err(anyhow::anyhow!("Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."));
+            }
+            let (keys, ids) = rest.split_at(rest.len() / 2);
+            let mut streams = vec![];
+            for (key, id) in keys.iter().zip(ids) {
+                let id = match unpack_bulk_str(id.clone())?.as_str() {
+                    "$" => None,
+                    id => Some(stream::StreamId::parse(id, 0)?),
+                };
+                streams.push((key.clone(), id));
             }
+            Ok(RedisCommand::XRead(count, block, streams))
+        }
+        "xlen" => match args.first() {
+            Some(key) if args.len() == 1 => Ok(RedisCommand::XLen(key.clone())),
+            _ => Err(anyhow::anyhow!(
+                "wrong number of arguments for 'xlen' command"
+            )),
+        },
+        "xdel" => {
+            if args.len() < 2 {
+                return Err(anyhow::anyhow!(
+                    "wrong number of arguments for 'xdel' command"
+                ));
+            }
+            let ids = args[1..]
+                .iter()
+                .map(|id| stream::StreamId::parse(&unpack_bulk_str(id.clone())?, 0))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(RedisCommand::XDel(args.first().unwrap().clone(), ids))
+        }
+        "xtrim" => {
+            let (key, kind, threshold) = match &args[..] {
+                [key, kind, threshold] => (key, kind, threshold),
+                // `~` asks for approximate trimming, which is done exactly
+                [key, kind, modifier, threshold] => {
+                    match unpack_bulk_str(modifier.clone())?.as_str() {
+                        "=" | "~" => (key, kind, threshold),
+                        _ => return Err(anyhow::anyhow!("syntax error")),
+                    }
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "wrong number of arguments for 'xtrim' command"
+                    ))
+                }
+            };
+            let threshold = unpack_bulk_str(threshold.clone())?;
+            let strategy = match unpack_bulk_str(kind.clone())?.to_lowercase().as_str() {
+                "maxlen" => match threshold.parse::<i64>() {
+                    Result::Ok(max_len) if max_len >= 0 => {
+                        stream::TrimStrategy::MaxLen(max_len as usize)
+                    }
+                    Result::Ok(_) => {
+                        return Err(anyhow::anyhow!("The MAXLEN argument must be >= 0."))
+                    }
+                    Result::Err(_) => {
+                        return Err(anyhow::anyhow!("value is not an integer or out of range"))
+                    }
+                },
+                "minid" => stream::TrimStrategy::MinId(stream::StreamId::parse(&threshold, 0)?),
+                _ => return Err(anyhow::anyhow!("syntax error")),
+            };
+            Ok(RedisCommand::XTrim(key.clone(), strategy))
+        }
+        "info" => {
+            Ok(RedisCommand::Info(args.first().cloned().unwrap_or_else(
+                || RedisValue::BulkString("default".to_owned()),
+            )))
         }
         // args.first().unwrap().clone(),
-        c => Err(anyhow::anyhow!("Cannot parse the command given: {:?}", c)), // panic!("Cannot handle command {}", c),
+        c => Err(anyhow::anyhow!("unknown command '{}'", c)), // panic!("Cannot handle command {}", c),
     }
 }
 
@@ -245,3 +2006,713 @@ fn unpack_bulk_str(value: RedisValue) -> Result<String> {
         _ => Err(anyhow::anyhow!("Expected command to be a bulk string")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bulk(s: &str) -> RedisValue {
+        RedisValue::BulkString(s.to_owned())
+    }
+
+    fn ok() -> RedisValue {
+        RedisValue::SimpleString("OK".to_owned())
+    }
+
+    async fn run(conn: &mut ConnState, args: &[&str]) -> RedisValue {
+        let frame = RedisValue::Array(args.iter().map(|arg| bulk(arg)).collect());
+        // a command that does not parse gets the error as its reply, as it
+        // would on a connection
+        match to_command(extract_command(frame).unwrap()) {
+            Result::Ok(command) => dispatch(command, conn).await.unwrap(),
+            Result::Err(e) => error::err(e),
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatches_commands_without_a_connection() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:1"));
+        // keys are prefixed, as tests share the keyspace
+        let cases: &[(&[&str], RedisValue)] = &[
+            (&["PING"], RedisValue::SimpleString("PONG".to_owned())),
+            (&["ECHO", "hey"], bulk("hey")),
+            (
+                &["ECHO"],
+                error::err("wrong number of arguments for 'echo' command"),
+            ),
+            (
+                &["GET", "dispatch:s", "extra"],
+                error::err("wrong number of arguments for 'get' command"),
+            ),
+            (&["SET", "dispatch:s", "v"], ok()),
+            (&["GET", "dispatch:s"], bulk("v")),
+            (&["SET", "dispatch:s", "w", "GET"], bulk("v")),
+            (&["GET", "dispatch:missing"], RedisValue::Null),
+            (
+                &["SET", "dispatch:s", "x", "PX", "0"],
+                error::err("invalid expire time in 'set' command"),
+            ),
+            (
+                &["SET", "dispatch:s", "x", "PXAT", "-1"],
+                error::err("invalid expire time in 'set' command"),
+            ),
+            (&["GET", "dispatch:s"], bulk("w")),
+            (&["XADD", "dispatch:x", "1-1", "f", "v"], bulk("1-1")),
+            (&["XLEN", "dispatch:x"], RedisValue::Integer(1)),
+            (&["GET", "dispatch:x"], error::wrong_type()),
+            (
+                &["XREAD", "STREAMS", "dispatch:x", "1-1"],
+                RedisValue::NullArray,
+            ),
+            (&["XADD", "dispatch:x", "1-2", "f", "v"], bulk("1-2")),
+            (
+                &["XREAD", "COUNT", "0", "STREAMS", "dispatch:x", "1-1"],
+                RedisValue::Array(vec![RedisValue::Array(vec![
+                    bulk("dispatch:x"),
+                    RedisValue::Array(vec![RedisValue::Array(vec![
+                        bulk("1-2"),
+                        RedisValue::Array(vec![bulk("f"), bulk("v")]),
+                    ])]),
+                ])]),
+            ),
+            (&["XADD", "dispatch:x", "1-3", "f", "v"], bulk("1-3")),
+            (
+                &["XDEL", "dispatch:x", "1-3", "1-9"],
+                RedisValue::Integer(1),
+            ),
+            (
+                &["XTRIM", "dispatch:x", "MAXLEN", "~", "1"],
+                RedisValue::Integer(1),
+            ),
+            (
+                &["XTRIM", "dispatch:x", "MINID", "2"],
+                RedisValue::Integer(1),
+            ),
+            (&["XLEN", "dispatch:x"], RedisValue::Integer(0)),
+            (
+                &["XADD", "dispatch:x", "1-3", "f", "v"],
+                error::err(
+                    "The ID specified in XADD is equal or smaller than the target stream top item",
+                ),
+            ),
+            (
+                &["DEBUG", "STRINGMATCH-LEN", "h?llo", "hello"],
+                RedisValue::Integer(1),
+            ),
+            (&["INFO", "nosuchsection"], bulk("")),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(&run(&mut conn, args).await, expected, "{:?}", args);
+        }
+    }
+
+    fn bit_range(start: i64, end: Option<i64>, in_bits: bool) -> Option<BitRange> {
+        Some(BitRange {
+            start,
+            end,
+            in_bits,
+        })
+    }
+
+    #[test]
+    fn bitpos_pads_with_zeros_only_without_an_end() {
+        let ones = [0xff, 0xff];
+        assert_eq!(bitpos(&ones, 0, None), 16);
+        assert_eq!(bitpos(&ones, 0, bit_range(1, None, false)), 16);
+        assert_eq!(bitpos(&ones, 0, bit_range(0, Some(1), false)), -1);
+        assert_eq!(bitpos(&ones, 0, bit_range(0, Some(-1), true)), -1);
+        assert_eq!(bitpos(&ones, 1, None), 0);
+    }
+
+    #[test]
+    fn bitpos_counts_negative_offsets_from_the_end() {
+        let bytes = [0x00, 0x0f, 0x00];
+        assert_eq!(bitpos(&bytes, 1, bit_range(-2, Some(-2), false)), 12);
+        assert_eq!(bitpos(&bytes, 1, bit_range(-1, None, false)), -1);
+        assert_eq!(bitpos(&bytes, 0, bit_range(-1, None, false)), 16);
+        assert_eq!(bitpos(&bytes, 1, bit_range(-100, Some(-1), false)), 12);
+        assert_eq!(bitpos(&bytes, 1, bit_range(-16, Some(-9), true)), 12);
+        assert_eq!(bitpos(&bytes, 1, bit_range(-1, Some(-2), false)), -1);
+    }
+
+    #[tokio::test]
+    async fn info_all_has_every_section() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:5"));
+        for args in [&["INFO", "all"][..], &["INFO", "EVERYTHING"], &["INFO"]] {
+            let RedisValue::BulkString(info) = run(&mut conn, args).await else {
+                panic!("INFO replies with a bulk string");
+            };
+            for header in ["# Server\r\n", "# Replication\r\n", "# Stats\r\n"] {
+                assert!(info.contains(header), "{:?}: {}", args, info);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_an_empty_command() {
+        assert!(extract_command(RedisValue::Array(vec![])).is_err());
+    }
+
+    #[tokio::test]
+    async fn debug_object_describes_streams() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:3"));
+        for seq in 1..=101 {
+            let id = format!("1-{}", seq);
+            run(&mut conn, &["XADD", "debug-object:x", &id, "f", "v"]).await;
+        }
+        let RedisValue::SimpleString(line) =
+            run(&mut conn, &["DEBUG", "OBJECT", "debug-object:x"]).await
+        else {
+            panic!("DEBUG OBJECT replies with a simple string");
+        };
+        assert!(line.contains(" encoding:stream "), "{}", line);
+        assert!(
+            line.ends_with(" radix-tree-keys:2 radix-tree-nodes:3 last-generated-id:1-101"),
+            "{}",
+            line
+        );
+        assert_eq!(
+            run(&mut conn, &["DEBUG", "OBJECT", "debug-object:missing"]).await,
+            error::err("no such key")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_sets_on_one_key_see_every_previous_value_once() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:4"));
+        run(
+            &mut conn,
+            &["SET", "concurrent:k", "initial", "PX", "100000"],
+        )
+        .await;
+
+        let tasks = (0..8)
+            .map(|task| {
+                tokio::spawn(async move {
+                    let mut conn = ConnState::new(&clients::register("127.0.0.1:4"));
+                    let mut previous = vec![];
+                    for i in 0..50 {
+                        let value = format!("{}:{}", task, i);
+                        let args = ["SET", "concurrent:k", &value, "GET", "KEEPTTL"];
+                        previous.push(run(&mut conn, &args).await);
+                    }
+                    previous
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut seen = vec![];
+        for task in tasks {
+            seen.extend(task.await.unwrap());
+        }
+        seen.push(run(&mut conn, &["GET", "concurrent:k"]).await);
+
+        // every write replaced exactly one other, so the values read back are
+        // the ones written, plus the initial one, each exactly once
+        let mut expected = vec![bulk("initial")];
+        for task in 0..8 {
+            expected.extend((0..50).map(|i| bulk(&format!("{}:{}", task, i))));
+        }
+        let sorted = |mut values: Vec<RedisValue>| {
+            values.sort_by_key(|value| format!("{:?}", value));
+            values
+        };
+        assert_eq!(sorted(seen), sorted(expected));
+        assert!(with_entry_mut(&bulk("concurrent:k"), |entry| entry
+            .as_ref()
+            .is_some_and(|(_, timeout)| timeout.is_some())));
+    }
+
+    #[tokio::test]
+    async fn rewritten_aof_replays_to_the_same_dataset() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:6"));
+        run(&mut conn, &["SET", "rewrite:s", "v", "PX", "100000"]).await;
+        // the newest entry is deleted, so the last id is past the last entry
+        for id in ["1-1", "1-2", "1-3"] {
+            run(&mut conn, &["XADD", "rewrite:x", id, "f", "v"]).await;
+        }
+        run(&mut conn, &["XDEL", "rewrite:x", "1-2", "1-3"]).await;
+        run(&mut conn, &["XADD", "rewrite:empty", "2-1", "f", "v"]).await;
+        run(&mut conn, &["XDEL", "rewrite:empty", "2-1"]).await;
+
+        let keys = ["rewrite:s", "rewrite:x", "rewrite:empty"].map(bulk);
+        let dataset = keys
+            .iter()
+            .map(|key| {
+                (
+                    key.clone(),
+                    with_entry_mut(key, |entry| entry.clone().unwrap()),
+                )
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!("aof-rewrite-{}.aof", std::process::id()));
+        let contents: String = dataset_as_commands(&dataset)
+            .into_iter()
+            .map(|command| command.serialize(Protocol::Resp2))
+            .collect();
+        std::fs::write(&path, contents).unwrap();
+
+        for key in &keys {
+            with_entry_mut(key, |entry| *entry = None);
+        }
+        for command in aof::load(path.to_str().unwrap()).unwrap() {
+            handle_command(to_command(extract_command(command).unwrap()).unwrap());
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(run(&mut conn, &["GET", "rewrite:s"]).await, bulk("v"));
+        assert!(with_entry_mut(&keys[0], |entry| entry
+            .as_ref()
+            .is_some_and(|(_, timeout)| timeout.is_some())));
+        assert_eq!(
+            run(&mut conn, &["XRANGE", "rewrite:x", "-", "+"]).await,
+            RedisValue::Array(vec![RedisValue::Array(vec![
+                bulk("1-1"),
+                RedisValue::Array(vec![bulk("f"), bulk("v")]),
+            ])])
+        );
+        for (key, last_id) in [("rewrite:x", "1-3"), ("rewrite:empty", "2-1")] {
+            let RedisValue::SimpleString(line) = run(&mut conn, &["DEBUG", "OBJECT", key]).await
+            else {
+                panic!("{} is restored", key);
+            };
+            assert!(
+                line.ends_with(&format!(" last-generated-id:{}", last_id)),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            run(&mut conn, &["XLEN", "rewrite:empty"]).await,
+            RedisValue::Integer(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn auth_checks_the_password_and_switches_user() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:7"));
+        run(
+            &mut conn,
+            &["ACL", "SETUSER", "auth-test", "on", ">secret", "+@all"],
+        )
+        .await;
+        conn.authenticated = false;
+        assert_eq!(refusal(&conn, "get"), Some(error::no_auth()));
+        assert_eq!(refusal(&conn, "auth"), None);
+        assert_eq!(refusal(&conn, "quit"), None);
+
+        assert_eq!(
+            run(&mut conn, &["AUTH", "auth-test", "wrong"]).await,
+            error::wrong_pass()
+        );
+        assert_eq!(
+            run(&mut conn, &["AUTH", "no-such-user", "secret"]).await,
+            error::wrong_pass()
+        );
+        assert!(!conn.authenticated);
+
+        assert_eq!(run(&mut conn, &["AUTH", "auth-test", "secret"]).await, ok());
+        assert!(conn.authenticated);
+        assert_eq!(conn.user, "auth-test");
+        assert_eq!(refusal(&conn, "get"), None);
+        assert_eq!(run(&mut conn, &["ACL", "WHOAMI"]).await, bulk("auth-test"));
+    }
+
+    #[tokio::test]
+    async fn hello_authenticates_only_with_its_auth_option() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:8"));
+        run(
+            &mut conn,
+            &["ACL", "SETUSER", "hello-test", "on", ">secret", "+@all"],
+        )
+        .await;
+        conn.authenticated = false;
+
+        assert_eq!(
+            run(&mut conn, &["HELLO", "3"]).await,
+            error::no_auth_hello()
+        );
+        assert_eq!(
+            run(&mut conn, &["HELLO", "3", "AUTH", "hello-test", "wrong"]).await,
+            error::wrong_pass()
+        );
+        assert_eq!(run(&mut conn, &["HELLO", "4"]).await, error::no_proto());
+        assert!(!conn.authenticated);
+        assert_eq!(conn.protocol, Protocol::Resp2);
+
+        assert_eq!(
+            run(&mut conn, &["HELLO", "3", "AUTH", "hello-test", "secret"]).await,
+            hello_reply(Protocol::Resp3)
+        );
+        assert!(conn.authenticated);
+        assert_eq!(conn.user, "hello-test");
+        assert_eq!(conn.protocol, Protocol::Resp3);
+    }
+
+    #[tokio::test]
+    async fn acl_setuser_limits_what_a_user_may_run() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:9"));
+        let setuser = [
+            "ACL",
+            "SETUSER",
+            "acl-test",
+            "on",
+            ">secret",
+            "+@connection",
+        ];
+        assert_eq!(run(&mut conn, &setuser).await, ok());
+        assert_eq!(run(&mut conn, &["AUTH", "acl-test", "secret"]).await, ok());
+        assert_eq!(refusal(&conn, "ping"), None);
+        assert_eq!(
+            refusal(&conn, "get"),
+            Some(RedisValue::Error(
+                "NOPERM User acl-test has no permissions to run the 'get' command".to_owned()
+            ))
+        );
+
+        assert_eq!(
+            run(&mut conn, &["ACL", "SETUSER", "acl-test", "bogus"]).await,
+            error::err("Error in ACL SETUSER modifier 'bogus': Syntax error")
+        );
+        assert_eq!(
+            run(
+                &mut conn,
+                &["ACL", "SETUSER", "acl-test", "+@read", "+@nope"]
+            )
+            .await,
+            error::err(
+                "Error in ACL SETUSER modifier '+@nope': Unknown command or category name in ACL"
+            )
+        );
+        // a rejected rule leaves the earlier ones unapplied too
+        assert!(refusal(&conn, "get").is_some());
+
+        run(&mut conn, &["ACL", "SETUSER", "acl-test", "off"]).await;
+        assert_eq!(
+            run(&mut conn, &["AUTH", "acl-test", "secret"]).await,
+            error::wrong_pass()
+        );
+    }
+
+    #[tokio::test]
+    async fn slowlog_keeps_commands_over_the_threshold() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        async fn run_logged(conn: &mut ConnState, args: &[&str]) -> RedisValue {
+            let frame = RedisValue::Array(args.iter().map(|arg| bulk(arg)).collect());
+            let (name, args) = extract_command(frame.clone()).unwrap();
+            let command = to_command((name.clone(), args)).unwrap();
+            dispatch_logged(&name.to_lowercase(), &frame, command, conn)
+                .await
+                .unwrap()
+        }
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:10"));
+        assert_eq!(run(&mut conn, &["SLOWLOG", "RESET"]).await, ok());
+
+        // a threshold of 0 logs everything
+        slowlog::configure(0, 128);
+        run_logged(&mut conn, &["PING"]).await;
+        assert_eq!(
+            run(&mut conn, &["SLOWLOG", "LEN"]).await,
+            RedisValue::Integer(1)
+        );
+        let RedisValue::Array(entries) = run(&mut conn, &["SLOWLOG", "GET"]).await else {
+            panic!("SLOWLOG GET replies with an array");
+        };
+        let RedisValue::Array(entry) = &entries[0] else {
+            panic!("each entry is an array");
+        };
+        assert_eq!(entry[3], RedisValue::Array(vec![bulk("PING")]));
+        assert_eq!(entry[4], bulk("127.0.0.1:10"));
+
+        // blocked time is not execution time, sleeping is
+        slowlog::configure(20_000, 128);
+        run_logged(&mut conn, &["PING"]).await;
+        let xread = ["XREAD", "BLOCK", "50", "STREAMS", "slowlog:x", "$"];
+        assert_eq!(run_logged(&mut conn, &xread).await, RedisValue::NullArray);
+        run_logged(&mut conn, &["DEBUG", "SLEEP", "0.03", "LOCAL"]).await;
+        assert_eq!(
+            run(&mut conn, &["SLOWLOG", "LEN"]).await,
+            RedisValue::Integer(2)
+        );
+        let RedisValue::Array(entries) = run(&mut conn, &["SLOWLOG", "GET", "1"]).await else {
+            panic!("SLOWLOG GET replies with an array");
+        };
+        let RedisValue::Array(entry) = &entries[0] else {
+            panic!("each entry is an array");
+        };
+        assert_eq!(entry[0], RedisValue::Integer(1));
+        assert_eq!(
+            entry[3],
+            RedisValue::Array(["DEBUG", "SLEEP", "0.03", "LOCAL"].map(bulk).to_vec())
+        );
+
+        assert_eq!(run(&mut conn, &["SLOWLOG", "RESET"]).await, ok());
+        assert_eq!(
+            run(&mut conn, &["SLOWLOG", "LEN"]).await,
+            RedisValue::Integer(0)
+        );
+
+        // passwords set with ACL SETUSER reach neither the slowlog nor MONITOR
+        acl::set_user(
+            acl::DEFAULT_USER,
+            &["on", "nopass", "allcommands"].map(String::from),
+        )
+        .unwrap();
+        let client = clients::register("127.0.0.1:15");
+        let mut monitor = monitor::subscribe();
+        let (mut peer, server) = tokio::io::duplex(1024);
+        slowlog::configure(0, 128);
+        let setuser = [
+            "ACL",
+            "SETUSER",
+            "slowlog:u",
+            "on",
+            ">secret",
+            "<old",
+            "#1a2b",
+        ];
+        let talk = async {
+            let frame = RedisValue::Array(setuser.map(bulk).to_vec());
+            peer.write_all(frame.serialize(Protocol::Resp2).as_bytes())
+                .await
+                .unwrap();
+            let mut reply = [0; 5];
+            peer.read_exact(&mut reply).await.unwrap();
+            drop(peer);
+        };
+        let (served, ()) = tokio::join!(handle_connection(server, &client), talk);
+        served.unwrap();
+        slowlog::configure(10_000, 128);
+        let redacted = [
+            "ACL",
+            "SETUSER",
+            "slowlog:u",
+            "on",
+            "(redacted)",
+            "(redacted)",
+            "(redacted)",
+        ];
+        let RedisValue::Array(entries) = run(&mut conn, &["SLOWLOG", "GET"]).await else {
+            panic!("SLOWLOG GET replies with an array");
+        };
+        let logged = entries.iter().any(|entry| {
+            matches!(entry, RedisValue::Array(entry)
+                if entry[3] == RedisValue::Array(redacted.map(bulk).to_vec()))
+        });
+        assert!(logged, "no redacted entry in {:?}", entries);
+        let line = std::iter::from_fn(|| monitor.try_recv().ok())
+            .find(|line| line.contains("127.0.0.1:15"))
+            .expect("the command is fed to MONITOR");
+        assert!(line.ends_with(
+            r#""ACL" "SETUSER" "slowlog:u" "on" "(redacted)" "(redacted)" "(redacted)""#
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_kill_interrupts_a_sleeping_client() {
+        let mut admin = ConnState::new(&clients::register("127.0.0.1:11"));
+        let target = clients::register("127.0.0.1:12");
+        let id = target.id.to_string();
+        let sleeping = tokio::spawn(async move {
+            let mut conn = ConnState::new(&target);
+            run(&mut conn, &["DEBUG", "SLEEP", "10", "LOCAL"]).await
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            run(&mut admin, &["CLIENT", "KILL", "ID", &id]).await,
+            RedisValue::Integer(1)
+        );
+        let reply = tokio::time::timeout(Duration::from_secs(1), sleeping)
+            .await
+            .expect("the sleep is cut short");
+        assert_eq!(reply.unwrap(), ok());
+        // the client was unregistered when the task dropped it
+        assert_eq!(
+            run(&mut admin, &["CLIENT", "KILL", "ID", &id]).await,
+            RedisValue::Integer(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn client_pause_holds_writes_until_it_ends_or_the_client_is_killed() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // as at startup, so the connection runs as the default user
+        acl::init(None);
+        let mut admin = ConnState::new(&clients::register("127.0.0.1:13"));
+        let client = clients::register("127.0.0.1:14");
+        let id = client.id.to_string();
+        let (mut peer, server) = tokio::io::duplex(1024);
+        let command = |args: &[&str]| RedisValue::Array(args.iter().map(|arg| bulk(arg)).collect());
+        let talk = async {
+            assert_eq!(
+                run(&mut admin, &["CLIENT", "PAUSE", "200", "WRITE"]).await,
+                ok()
+            );
+            let started = Instant::now();
+            let mut reply = [0; 5];
+            let get = command(&["GET", "pause:k"]).serialize(Protocol::Resp2);
+            peer.write_all(get.as_bytes()).await.unwrap();
+            peer.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"$-1\r\n");
+            assert!(started.elapsed() < Duration::from_millis(150));
+
+            let set = command(&["SET", "pause:k", "v"]).serialize(Protocol::Resp2);
+            peer.write_all(set.as_bytes()).await.unwrap();
+            peer.read_exact(&mut reply).await.unwrap();
+            assert_eq!(&reply, b"+OK\r\n");
+            assert!(started.elapsed() >= Duration::from_millis(150));
+
+            // a client held back by a pause can still be killed
+            run(&mut admin, &["CLIENT", "PAUSE", "5000", "WRITE"]).await;
+            let set = command(&["SET", "pause:killed", "v"]).serialize(Protocol::Resp2);
+            peer.write_all(set.as_bytes()).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(
+                run(&mut admin, &["CLIENT", "KILL", "ID", &id]).await,
+                RedisValue::Integer(1)
+            );
+        };
+        let serving =
+            tokio::time::timeout(Duration::from_secs(2), handle_connection(server, &client));
+        let (served, ()) = tokio::join!(serving, talk);
+        served.expect("the killed connection closes").unwrap();
+        assert_eq!(
+            run(&mut admin, &["GET", "pause:killed"]).await,
+            RedisValue::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn replicas_are_tracked_per_client() {
+        fn connected_replicas() -> usize {
+            replication::info()
+                .lines()
+                .find_map(|line| line.strip_prefix("connected_slaves:"))
+                .and_then(|count| count.parse().ok())
+                .unwrap()
+        }
+        let before = connected_replicas();
+        // unix socket clients all share one address
+        let first = clients::register("/tmp/replicas-test.sock:0");
+        let second = clients::register("/tmp/replicas-test.sock:0");
+        for client in [&first, &second] {
+            let mut conn = ConnState::new(client);
+            run(&mut conn, &["REPLCONF", "listening-port", "6380"]).await;
+        }
+        assert_eq!(connected_replicas(), before + 2);
+        drop(first);
+        assert_eq!(connected_replicas(), before + 1);
+        drop(second);
+        assert_eq!(connected_replicas(), before);
+    }
+
+    #[tokio::test]
+    async fn xrange_handles_exclusive_bounds_at_sequence_edges() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:15"));
+        let max = "1-18446744073709551615";
+        for id in ["1-0", "1-1", max, "2-0", "2-1"] {
+            run(&mut conn, &["XADD", "xrange:x", id, "f", "v"]).await;
+        }
+        let entries = |ids: &[&str]| {
+            RedisValue::Array(
+                ids.iter()
+                    .map(|id| {
+                        RedisValue::Array(vec![
+                            bulk(id),
+                            RedisValue::Array(vec![bulk("f"), bulk("v")]),
+                        ])
+                    })
+                    .collect(),
+            )
+        };
+        let after_max = format!("({}", max);
+        let cases: &[(&[&str], RedisValue)] = &[
+            (
+                &["XRANGE", "xrange:x", "-", "+"],
+                entries(&["1-0", "1-1", max, "2-0", "2-1"]),
+            ),
+            (
+                &["XRANGE", "xrange:x", "(1-0", "+", "COUNT", "2"],
+                entries(&["1-1", max]),
+            ),
+            (
+                &["XRANGE", "xrange:x", &after_max, "+"],
+                entries(&["2-0", "2-1"]),
+            ),
+            (&["XRANGE", "xrange:x", "-", "(1-0"], entries(&[])),
+            (
+                &["XRANGE", "xrange:x", "-", "(2-0"],
+                entries(&["1-0", "1-1", max]),
+            ),
+            (
+                &["XREVRANGE", "xrange:x", "+", "-", "COUNT", "2"],
+                entries(&["2-1", "2-0"]),
+            ),
+            (
+                &["XREVRANGE", "xrange:x", "(2-0", "(1-0"],
+                entries(&[max, "1-1"]),
+            ),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(&run(&mut conn, args).await, expected, "{:?}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_xread_wakes_up_on_xadd() {
+        let mut conn = ConnState::new(&clients::register("127.0.0.1:16"));
+        run(&mut conn, &["XADD", "xread-block:x", "1-1", "f", "old"]).await;
+
+        // `$` only sees entries added after the command runs
+        let blocked = tokio::spawn(async {
+            let client = clients::register("127.0.0.1:17");
+            let mut conn = ConnState::new(&client);
+            let args = ["XREAD", "BLOCK", "0", "STREAMS", "xread-block:x", "$"];
+            run(&mut conn, &args).await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+        run(&mut conn, &["XADD", "xread-block:x", "1-2", "f", "new"]).await;
+        let reply = tokio::time::timeout(Duration::from_secs(1), blocked)
+            .await
+            .expect("XADD wakes the reader");
+        assert_eq!(
+            reply.unwrap(),
+            RedisValue::Array(vec![RedisValue::Array(vec![
+                bulk("xread-block:x"),
+                RedisValue::Array(vec![RedisValue::Array(vec![
+                    bulk("1-2"),
+                    RedisValue::Array(vec![bulk("f"), bulk("new")]),
+                ])]),
+            ])])
+        );
+
+        let started = Instant::now();
+        let args = ["XREAD", "BLOCK", "50", "STREAMS", "xread-block:x", "$"];
+        assert_eq!(run(&mut conn, &args).await, RedisValue::NullArray);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn dispatch_updates_connection_state() {
+        let client = clients::register("127.0.0.1:2");
+        let mut conn = ConnState::new(&client);
+
+        assert_eq!(
+            run(&mut conn, &["CLIENT", "ID"]).await,
+            RedisValue::Integer(client.id as i64)
+        );
+        assert_eq!(run(&mut conn, &["ACL", "WHOAMI"]).await, bulk("default"));
+
+        run(&mut conn, &["HELLO", "3"]).await;
+        assert_eq!(conn.protocol, Protocol::Resp3);
+
+        assert_eq!(run(&mut conn, &["MONITOR"]).await, ok());
+        assert!(conn.monitor);
+
+        assert_eq!(run(&mut conn, &["QUIT"]).await, ok());
+        assert!(conn.quit);
+    }
+}
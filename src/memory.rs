@@ -0,0 +1,89 @@
+use crate::resp::RedisValue;
+use crate::stream::Stream;
+use crate::{StoredEntry, StoredValue};
+
+// rough per-key cost of the hashmap slot, the key/value headers and the
+// allocator's bookkeeping, in the same ballpark as a real Redis dictEntry
+const ENTRY_OVERHEAD: usize = 48;
+// extra cost of a key that carries an expiry
+const EXPIRY_OVERHEAD: usize = 24;
+
+/// Approximate number of bytes a key and its value occupy.
+pub fn usage(key: &RedisValue, entry: &StoredEntry) -> usize {
+    let (value, timeout) = entry;
+    let mut size = ENTRY_OVERHEAD
+        + value_size(key)
+        + match value {
+            StoredValue::String(value) => value_size(value),
+            StoredValue::Stream(stream) => stream_size(stream),
+        };
+    if timeout.is_some() {
+        size += EXPIRY_OVERHEAD;
+    }
+    size
+}
+
+fn stream_size(stream: &Stream) -> usize {
+    stream
+        .entries
+        .values()
+        .map(|fields| {
+            // the id, plus a pointer and length for every field and value
+            16 + fields
+                .iter()
+                .map(|(field, value)| 2 * std::mem::size_of::<String>() + field.len() + value.len())
+                .sum::<usize>()
+        })
+        .sum()
+}
+
+fn value_size(value: &RedisValue) -> usize {
+    match value {
+        RedisValue::SimpleString(s) | RedisValue::Error(s) | RedisValue::BulkString(s) => s.len(),
+        RedisValue::Integer(_) => std::mem::size_of::<i64>(),
+        RedisValue::Array(items) => items
+            .iter()
+            .map(|item| std::mem::size_of::<RedisValue>() + value_size(item))
+            .sum(),
+        RedisValue::Null | RedisValue::NullArray => 0,
+        RedisValue::Map(pairs) => pairs
+            .iter()
+            .map(|(key, value)| {
+                2 * std::mem::size_of::<RedisValue>() + value_size(key) + value_size(value)
+            })
+            .sum(),
+    }
+}
+
+/// Totals across the keyspace, backing MEMORY STATS and MEMORY DOCTOR.
+pub struct DatasetStats {
+    pub total_bytes: usize,
+    pub keys: usize,
+    pub expires: usize,
+}
+
+pub fn dataset_stats<'a>(
+    entries: impl Iterator<Item = (&'a RedisValue, &'a StoredEntry)>,
+) -> DatasetStats {
+    let mut stats = DatasetStats {
+        total_bytes: 0,
+        keys: 0,
+        expires: 0,
+    };
+    for (key, entry) in entries {
+        stats.total_bytes += usage(key, entry);
+        stats.keys += 1;
+        if entry.1.is_some() {
+            stats.expires += 1;
+        }
+    }
+    stats
+}
+
+pub fn doctor(stats: &DatasetStats) -> String {
+    if stats.keys == 0 {
+        "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in these conditions.".to_owned()
+    } else {
+        "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this base.".to_owned()
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+
+use crate::resp::RedisValue;
+
+// how many lines a MONITOR connection may fall behind before it is closed
+static OUTPUT_BUFFER_LIMIT: AtomicUsize = AtomicUsize::new(1024);
+
+lazy_static::lazy_static! {
+    static ref MONITORS: broadcast::Sender<String> =
+        broadcast::channel(OUTPUT_BUFFER_LIMIT.load(Ordering::Relaxed)).0;
+}
+
+/// Sets the output buffer limit. Must be called before the first
+/// `subscribe` or `feed`, as the channel is sized when it is created.
+pub fn configure(output_buffer_limit: usize) {
+    OUTPUT_BUFFER_LIMIT.store(output_buffer_limit, Ordering::Relaxed);
+}
+
+pub fn subscribe() -> broadcast::Receiver<String> {
+    MONITORS.subscribe()
+}
+
+/// Sends a processed command to every MONITOR connection, formatted as
+/// `<timestamp> [<db> <addr>] "cmd" "arg"...`.
+pub fn feed(command: &RedisValue, addr: &str) {
+    if MONITORS.receiver_count() == 0 {
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("clock is before the unix epoch");
+    let mut line = format!("{}.{:06} [0 {}]", now.as_secs(), now.subsec_micros(), addr);
+    if let RedisValue::Array(items) = command {
+        for item in items {
+            if let RedisValue::BulkString(s) = item {
+                line.push(' ');
+                line.push_str(&quote(s));
+            }
+        }
+    }
+    // no receivers left between the check and the send is fine
+    let _ = MONITORS.send(line);
+}
+
+/// Quotes an argument the way Redis' sdscatrepr does, so every line stays a
+/// single line.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for byte in s.bytes() {
+        match byte {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
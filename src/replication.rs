@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::util::random_hex_id;
+
+/// What a replica told us about itself with REPLCONF.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaInfo {
+    pub listening_port: Option<u16>,
+    pub capabilities: Vec<String>,
+}
+
+lazy_static::lazy_static! {
+    static ref MASTER_REPLID: Mutex<String> = Mutex::new(random_hex_id());
+    // replicas by client id, with the address of their connection to us;
+    // unix socket clients all share one address
+    static ref REPLICAS: Mutex<BTreeMap<u64, (String, ReplicaInfo)>> = Mutex::new(BTreeMap::new());
+}
+
+/// Replaces the replication id, so replicas have to fully resync.
+pub fn change_replid() {
+    *MASTER_REPLID.lock().unwrap() = random_hex_id();
+}
+
+/// Records the latest REPLCONF details of the replica with client id `id`,
+/// connected from `addr`.
+pub fn register_replica(id: u64, addr: &str, info: ReplicaInfo) {
+    REPLICAS.lock().unwrap().insert(id, (addr.to_owned(), info));
+}
+
+/// Called when a connection closes; a no-op for non-replica connections.
+pub fn remove_replica(id: u64) {
+    REPLICAS.lock().unwrap().remove(&id);
+}
+
+/// The `INFO replication` section.
+pub fn info() -> String {
+    let replicas = REPLICAS.lock().unwrap();
+    let mut lines = vec![
+        "role:master".to_owned(),
+        format!("connected_slaves:{}", replicas.len()),
+    ];
+
+    // in client id order, so the slaveN numbering is stable between calls
+    for (i, (addr, replica)) in replicas.values().enumerate() {
+        // replicas that never sent listening-port are listed by the port
+        // they connected from
+        let (ip, connected_port) = addr.rsplit_once(':').unwrap_or((addr, "0"));
+        let port = match replica.listening_port {
+            Some(port) => port.to_string(),
+            None => connected_port.to_owned(),
+        };
+        lines.push(format!(
+            "slave{}:ip={},port={},state=online,offset=0,lag=0",
+            i, ip, port
+        ));
+    }
+
+    lines.push(format!("master_replid:{}", MASTER_REPLID.lock().unwrap()));
+    lines.push("master_repl_offset:0".to_owned());
+    lines.join("\r\n")
+}
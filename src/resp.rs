@@ -1,87 +1,208 @@
 use anyhow::Result;
-use bytes::BytesMut;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 #[derive(Debug, PartialEq, Hash, Eq, Clone)]
 pub enum RedisValue {
     SimpleString(String),
-    // Error(String),
+    Error(String),
     Integer(i64),
     BulkString(String),
     Array(Vec<RedisValue>),
+    Null,
+    // the null array, `*-1`, which some commands send instead of the null
+    // bulk string; RESP3 has a single null for both
+    NullArray,
+    // RESP3 map; sent to RESP2 clients as a flat key/value array
+    Map(Vec<(RedisValue, RedisValue)>),
 }
-pub struct RespHandler {
-    stream: TcpStream,
+
+/// The parse error for a buffer that ends before the value does. It is not
+/// malformed; reading more bytes can complete it.
+#[derive(Debug, thiserror::Error)]
+#[error("incomplete value")]
+pub struct Incomplete;
+
+/// The RESP version a connection negotiated with HELLO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn from_version(version: i64) -> Option<Protocol> {
+        match version {
+            2 => Some(Protocol::Resp2),
+            3 => Some(Protocol::Resp3),
+            _ => None,
+        }
+    }
+
+    pub fn version(self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+/// Reads and writes RESP values over any byte stream: TCP, a Unix socket,
+/// or an in-memory pipe.
+pub struct RespHandler<S: AsyncRead + AsyncWrite + Unpin> {
+    stream: S,
     buffer: BytesMut,
+    // replies queued since the last flush; pipelined commands that arrived
+    // in one read get their replies back in one write
+    output: Vec<u8>,
 }
 
 impl RedisValue {
-    pub fn serialize(self) -> String {
+    pub fn serialize(self, protocol: Protocol) -> String {
         match self {
             RedisValue::SimpleString(s) => format!("+{}\r\n", s),
+            RedisValue::Error(s) => format!("-{}\r\n", s),
 
-            RedisValue::BulkString(s) => match s.as_str() {
+            RedisValue::Integer(i) => format!(":{}\r\n", i),
+
+            // the length is in bytes, not characters
+            RedisValue::BulkString(s) => format!("${}\r\n{}\r\n", s.len(), s),
+            RedisValue::Array(items) => {
+                let mut out = format!("*{}\r\n", items.len());
+                for item in items {
+                    out.push_str(&item.serialize(protocol));
+                }
+                out
+            }
+            RedisValue::Null => match protocol {
                 // this is null bulk string
-                "-1" => format!("$-1\r\n"),
-                val => format!("${}\r\n{}\r\n", s.chars().count(), val),
+                Protocol::Resp2 => "$-1\r\n".to_string(),
+                Protocol::Resp3 => "_\r\n".to_string(),
+            },
+            RedisValue::NullArray => match protocol {
+                Protocol::Resp2 => "*-1\r\n".to_string(),
+                Protocol::Resp3 => "_\r\n".to_string(),
             },
-            _ => panic!("Unsupported value for serialize"),
+            RedisValue::Map(pairs) => {
+                let mut out = match protocol {
+                    Protocol::Resp2 => format!("*{}\r\n", pairs.len() * 2),
+                    Protocol::Resp3 => format!("%{}\r\n", pairs.len()),
+                };
+                for (key, value) in pairs {
+                    out.push_str(&key.serialize(protocol));
+                    out.push_str(&value.serialize(protocol));
+                }
+                out
+            }
         }
     }
 }
 
-impl RespHandler {
-    pub fn new(stream: TcpStream) -> Self {
+impl<S: AsyncRead + AsyncWrite + Unpin> RespHandler<S> {
+    pub fn new(stream: S) -> Self {
         RespHandler {
             stream,
             buffer: BytesMut::with_capacity(512),
+            output: Vec::new(),
         }
     }
+    /// The next value from the peer, or `None` once it has closed the
+    /// connection. Values already buffered are returned without touching the
+    /// socket; queued replies are flushed before waiting on it for more.
     pub async fn read_value(&mut self) -> Result<Option<RedisValue>> {
-        let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
-        if bytes_read == 0 {
-            return Ok(None);
+        loop {
+            match parse_message(&self.buffer) {
+                Ok((v, len)) => {
+                    self.buffer.advance(len);
+                    return Ok(Some(v));
+                }
+                Err(e) if e.is::<Incomplete>() => {}
+                Err(e) => return Err(e),
+            }
+            self.flush().await?;
+            let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+            if bytes_read == 0 {
+                return Ok(None);
+            }
         }
-        let (v, _) = parse_message(self.buffer.split())?;
-        Ok(Some(v))
     }
-    pub async fn write_value(&mut self, value: RedisValue) -> Result<()> {
-        self.stream.write(value.serialize().as_bytes()).await?;
+    /// Queues a reply; it is sent by the next `flush`, or once the handler
+    /// runs out of buffered values to read.
+    pub fn queue_value(&mut self, value: RedisValue, protocol: Protocol) {
+        self.output
+            .extend_from_slice(value.serialize(protocol).as_bytes());
+    }
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+        self.stream.write_all(&self.output).await?;
+        self.output.clear();
+        // a buffered stream would otherwise hold the tail of the reply back
+        self.stream.flush().await?;
         Ok(())
     }
+    pub async fn write_value(&mut self, value: RedisValue, protocol: Protocol) -> Result<()> {
+        self.queue_value(value, protocol);
+        self.flush().await
+    }
 }
 
-fn parse_message(buffer: BytesMut) -> Result<(RedisValue, usize)> {
+pub fn parse_message(buffer: &[u8]) -> Result<(RedisValue, usize)> {
     // eprintln!("buffer: {:?}", buffer);
-    match buffer[0] as char {
-        // ':' => parse_integer(&buffer),
+    let Some(&first) = buffer.first() else {
+        return Err(Incomplete.into());
+    };
+    match first as char {
+        ':' => parse_integer(buffer),
         '+' => parse_simple_string(buffer),
+        '-' => parse_error(buffer),
         '*' => parse_array(buffer),
         '$' => parse_bulk_string(buffer),
         _ => Err(anyhow::anyhow!("Not a known value type {:?}", buffer)),
     }
 }
 
-fn parse_simple_string(buffer: BytesMut) -> Result<(RedisValue, usize)> {
+fn parse_simple_string(buffer: &[u8]) -> Result<(RedisValue, usize)> {
     if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let string = String::from_utf8(line.to_vec()).unwrap();
+        let string = String::from_utf8(line.to_vec())?;
         return Ok((RedisValue::SimpleString(string), len + 1));
     }
-    return Err(anyhow::anyhow!("Invalid string {:?}", buffer));
+    Err(Incomplete.into())
 }
 
-fn parse_bulk_string(buffer: BytesMut) -> Result<(RedisValue, usize)> {
+fn parse_error(buffer: &[u8]) -> Result<(RedisValue, usize)> {
+    if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
+        let message = String::from_utf8(line.to_vec())?;
+        return Ok((RedisValue::Error(message), len + 1));
+    }
+    Err(Incomplete.into())
+}
+
+fn parse_bulk_string(buffer: &[u8]) -> Result<(RedisValue, usize)> {
     let (bulk_str_len, bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
         let bulk_str_len = parse_int(line)?;
         (bulk_str_len, len + 1)
     } else {
-        return Err(anyhow::anyhow!("Invalid array format {:?}", buffer));
+        return Err(Incomplete.into());
     };
+    // $-1 is the RESP2 null
+    if bulk_str_len < 0 {
+        return Ok((RedisValue::Null, bytes_consumed));
+    }
     let end_of_bulk_str = bytes_consumed + bulk_str_len as usize;
     let total_parsed = end_of_bulk_str + 2;
+    // the declared length must land exactly on the closing CRLF
+    match buffer.get(end_of_bulk_str..total_parsed) {
+        Some(b"\r\n") => {}
+        Some(_) => {
+            return Err(anyhow::anyhow!(
+                "Bulk string does not end with CRLF {:?}",
+                buffer
+            ))
+        }
+        None => return Err(Incomplete.into()),
+    }
     Ok((
         RedisValue::BulkString(String::from_utf8(
             buffer[bytes_consumed..end_of_bulk_str].to_vec(),
@@ -90,21 +211,25 @@ fn parse_bulk_string(buffer: BytesMut) -> Result<(RedisValue, usize)> {
     ))
 }
 
-fn parse_array(buffer: BytesMut) -> Result<(RedisValue, usize)> {
+fn parse_array(buffer: &[u8]) -> Result<(RedisValue, usize)> {
     let (array_length, mut bytes_consumed) =
         if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
             let array_length = parse_int(line)?;
             (array_length, len + 1)
         } else {
-            return Err(anyhow::anyhow!("Invalid array format {:?}", buffer));
+            return Err(Incomplete.into());
         };
+    // *-1 is the null array, which RESP2 has besides the null bulk string
+    if array_length < 0 {
+        return Ok((RedisValue::NullArray, bytes_consumed));
+    }
     let mut items = vec![];
     for _ in 0..array_length {
-        let (array_item, len) = parse_message(BytesMut::from(&buffer[bytes_consumed..]))?;
+        let (array_item, len) = parse_message(&buffer[bytes_consumed..])?;
         items.push(array_item);
         bytes_consumed += len;
     }
-    return Ok((RedisValue::Array(items), bytes_consumed));
+    Ok((RedisValue::Array(items), bytes_consumed))
 }
 
 fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
@@ -113,16 +238,17 @@ fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
             return Some((&buffer[0..(i - 1)], i + 1));
         }
     }
-    return None;
+    None
 }
 
 pub fn parse_integer(buffer: &[u8]) -> Result<(RedisValue, usize)> {
-    if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        if let Ok(int_val) = parse_int_with_sign(line) {
-            return Ok((RedisValue::Integer(int_val), len + 1));
-        }
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Err(Incomplete.into());
+    };
+    match parse_int_with_sign(line) {
+        Ok(int_val) => Ok((RedisValue::Integer(int_val), len + 1)),
+        Err(_) => Err(anyhow::anyhow!("Invalid integer {:?}", buffer)),
     }
-    return Err(anyhow::anyhow!("Invalid integer {:?}", buffer));
 }
 
 pub fn parse_int_with_sign(line: &[u8]) -> Result<i64> {
@@ -130,19 +256,347 @@ pub fn parse_int_with_sign(line: &[u8]) -> Result<i64> {
         return Err(anyhow::anyhow!("Empty integer value"));
     }
 
-    let (sign, start_index) = match line[0] {
-        b'+' => (1, 1),
-        b'-' => (-1, 1),
-        _ => (1, 0),
-    };
-
-    let unsigned_val = std::str::from_utf8(&line[start_index..])?
-        .parse::<u64>()
-        .map_err(|e| anyhow::anyhow!("Invalid integer: {}", e))?;
-
-    Ok(sign * (unsigned_val as i64))
+    // parsing the sign along with the digits keeps i64::MIN in range
+    std::str::from_utf8(line)?
+        .parse::<i64>()
+        .map_err(|e| anyhow::anyhow!("Invalid integer: {}", e))
 }
 
 fn parse_int(buffer: &[u8]) -> Result<i64> {
     Ok(std::str::from_utf8(buffer)?.parse::<i64>()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_over_an_in_memory_pipe() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = RespHandler::new(client);
+        let mut server = RespHandler::new(server);
+
+        let ping = RedisValue::Array(vec![RedisValue::BulkString("PING".to_owned())]);
+        client
+            .write_value(ping.clone(), Protocol::Resp2)
+            .await
+            .unwrap();
+        assert_eq!(server.read_value().await.unwrap(), Some(ping));
+
+        server
+            .write_value(RedisValue::SimpleString("PONG".to_owned()), Protocol::Resp2)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.read_value().await.unwrap(),
+            Some(RedisValue::SimpleString("PONG".to_owned()))
+        );
+
+        drop(server);
+        assert_eq!(client.read_value().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn writes_large_replies_in_full() {
+        // far bigger than the pipe, so the write has to happen in pieces
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut client = RespHandler::new(client);
+        let payload = "x".repeat(64 * 1024);
+        let expected = RedisValue::BulkString(payload.clone()).serialize(Protocol::Resp2);
+
+        let writer = tokio::spawn(async move {
+            client
+                .write_value(RedisValue::BulkString(payload), Protocol::Resp2)
+                .await
+                .unwrap();
+        });
+        let mut received = vec![0; expected.len()];
+        server.read_exact(&mut received).await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(received, expected.as_bytes());
+    }
+
+    /// Counts the writes that reach the wrapped stream.
+    struct CountingWrites<S> {
+        inner: S,
+        writes: usize,
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for CountingWrites<S> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for CountingWrites<S> {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.writes += 1;
+            std::pin::Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_a_pipelined_batch_with_one_write() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let mut server = RespHandler::new(CountingWrites {
+            inner: server,
+            writes: 0,
+        });
+
+        let ping = RedisValue::Array(vec![RedisValue::BulkString("PING".to_owned())]);
+        let batch = ping.clone().serialize(Protocol::Resp2).repeat(16);
+        client.write_all(batch.as_bytes()).await.unwrap();
+        for _ in 0..16 {
+            assert_eq!(server.read_value().await.unwrap(), Some(ping.clone()));
+            server.queue_value(RedisValue::SimpleString("PONG".to_owned()), Protocol::Resp2);
+        }
+        assert_eq!(server.stream.writes, 0);
+        server.flush().await.unwrap();
+        assert_eq!(server.stream.writes, 1);
+
+        let mut replies = vec![0; 16 * 7];
+        client.read_exact(&mut replies).await.unwrap();
+        assert_eq!(replies, "+PONG\r\n".repeat(16).as_bytes());
+    }
+
+    #[tokio::test]
+    async fn reads_a_value_split_across_reads() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut server = RespHandler::new(server);
+
+        let echo = RedisValue::Array(vec![
+            RedisValue::BulkString("ECHO".to_owned()),
+            RedisValue::BulkString("hey".to_owned()),
+        ]);
+        let frame = echo.clone().serialize(Protocol::Resp2);
+        let (head, tail) = frame.as_bytes().split_at(10);
+        client.write_all(head).await.unwrap();
+        let tail = tail.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            client.write_all(&tail).await.unwrap();
+        });
+        assert_eq!(server.read_value().await.unwrap(), Some(echo));
+    }
+
+    /// Checks one write per pipelined batch beats a write per reply over a
+    /// loopback TCP connection. Run with
+    /// `cargo test --release -- --ignored pipelined_replies`.
+    #[tokio::test]
+    #[ignore]
+    async fn benchmark_pipelined_replies() {
+        const BATCHES: usize = 20_000;
+        const PIPELINE: usize = 16;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut writer = RespHandler::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        let (mut reader, _) = listener.accept().await.unwrap();
+        let drain = tokio::spawn(async move {
+            let mut sink = vec![0; 64 * 1024];
+            while reader.read(&mut sink).await.unwrap() > 0 {}
+        });
+
+        let reply = || RedisValue::SimpleString("PONG".to_owned());
+        let started = std::time::Instant::now();
+        for _ in 0..BATCHES {
+            for _ in 0..PIPELINE {
+                writer.write_value(reply(), Protocol::Resp2).await.unwrap();
+            }
+        }
+        let unbatched = started.elapsed();
+
+        let started = std::time::Instant::now();
+        for _ in 0..BATCHES {
+            for _ in 0..PIPELINE {
+                writer.queue_value(reply(), Protocol::Resp2);
+            }
+            writer.flush().await.unwrap();
+        }
+        let batched = started.elapsed();
+
+        drop(writer);
+        drain.await.unwrap();
+        assert!(
+            batched < unbatched,
+            "{} batches of {}: a write per reply {:?}, a write per batch {:?}",
+            BATCHES,
+            PIPELINE,
+            unbatched,
+            batched
+        );
+    }
+
+    #[test]
+    fn parses_both_null_forms() {
+        assert_eq!(parse_message(b"$-1\r\n").unwrap(), (RedisValue::Null, 5));
+        assert_eq!(
+            parse_message(b"*-1\r\n").unwrap(),
+            (RedisValue::NullArray, 5)
+        );
+        // only the header is consumed
+        assert_eq!(
+            parse_message(b"*2\r\n$-1\r\n*-1\r\n").unwrap(),
+            (
+                RedisValue::Array(vec![RedisValue::Null, RedisValue::NullArray]),
+                14
+            )
+        );
+    }
+
+    #[test]
+    fn parses_empty_bulk_strings_and_arrays() {
+        assert_eq!(
+            parse_message(b"$0\r\n\r\n").unwrap(),
+            (RedisValue::BulkString(String::new()), 6)
+        );
+        assert_eq!(
+            parse_message(b"*0\r\n").unwrap(),
+            (RedisValue::Array(vec![]), 4)
+        );
+        // SET k ""
+        assert_eq!(
+            parse_message(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$0\r\n\r\n*0\r\n").unwrap(),
+            (
+                RedisValue::Array(vec![
+                    RedisValue::BulkString("SET".to_owned()),
+                    RedisValue::BulkString("k".to_owned()),
+                    RedisValue::BulkString(String::new()),
+                ]),
+                26
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_bulk_strings_not_matching_their_length() {
+        let corrupt = |frame: &[u8]| parse_message(frame).is_err_and(|e| !e.is::<Incomplete>());
+        // longer than declared
+        assert!(corrupt(b"$3\r\nabcd\r\n"));
+        assert!(corrupt(b"*1\r\n$3\r\nabcX\r\n"));
+        // cut off before the data ends, which more bytes could still fix
+        assert!(parse_message(b"$10\r\nabc").is_err_and(|e| e.is::<Incomplete>()));
+        assert!(parse_message(b"$5\r\nabc\r\n").is_err_and(|e| e.is::<Incomplete>()));
+    }
+
+    #[test]
+    fn rejects_an_empty_buffer() {
+        assert!(parse_message(b"").is_err_and(|e| e.is::<Incomplete>()));
+        // an array whose items have not arrived yet
+        assert!(parse_message(b"*2\r\n$1\r\na\r\n").is_err_and(|e| e.is::<Incomplete>()));
+    }
+
+    /// A xorshift generator, so the round-trip test is reproducible and
+    /// needs no extra crates.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    fn arbitrary_text(rng: &mut Rng, line: bool) -> String {
+        // multi-byte characters catch lengths counted in chars, and CR/LF
+        // inside a bulk string catch a parser looking for the line end
+        const CHARS: &[char] = &['a', 'Z', '0', ' ', '-', '$', '*', 'é', '€', '\r', '\n'];
+        let pool = if line { &CHARS[..9] } else { CHARS };
+        (0..rng.below(12))
+            .map(|_| pool[rng.below(pool.len() as u64) as usize])
+            .collect()
+    }
+
+    fn arbitrary_value(rng: &mut Rng, depth: u32) -> RedisValue {
+        match rng.below(if depth == 0 { 6 } else { 8 }) {
+            0 => RedisValue::SimpleString(arbitrary_text(rng, true)),
+            1 => RedisValue::Error(arbitrary_text(rng, true)),
+            2 => RedisValue::Integer(match rng.below(4) {
+                0 => i64::MIN,
+                1 => i64::MAX,
+                _ => rng.next() as i64 % 1000,
+            }),
+            3 => RedisValue::BulkString(arbitrary_text(rng, false)),
+            4 => RedisValue::Null,
+            5 => RedisValue::NullArray,
+            6 => RedisValue::Array(
+                (0..rng.below(5))
+                    .map(|_| arbitrary_value(rng, depth - 1))
+                    .collect(),
+            ),
+            _ => RedisValue::Map(
+                (0..rng.below(3))
+                    .map(|_| {
+                        (
+                            arbitrary_value(rng, depth - 1),
+                            arbitrary_value(rng, depth - 1),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// `value` as a RESP2 client sees it, which is a map sent as a flat
+    /// array of keys and values.
+    fn as_resp2(value: RedisValue) -> RedisValue {
+        match value {
+            RedisValue::Array(items) => {
+                RedisValue::Array(items.into_iter().map(as_resp2).collect())
+            }
+            RedisValue::Map(pairs) => RedisValue::Array(
+                pairs
+                    .into_iter()
+                    .flat_map(|(key, value)| [as_resp2(key), as_resp2(value)])
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    #[test]
+    fn parse_message_inverts_serialize() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let value = arbitrary_value(&mut rng, 3);
+            let mut bytes = value.clone().serialize(Protocol::Resp2).into_bytes();
+            let len = bytes.len();
+            // whatever follows the frame must not be consumed
+            bytes.extend_from_slice(b"+next\r\n");
+            assert_eq!(
+                parse_message(&bytes).unwrap(),
+                (as_resp2(value.clone()), len),
+                "{:?}",
+                value
+            );
+        }
+    }
+}
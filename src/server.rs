@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Instant;
+
+use crate::util::random_hex_id;
+use crate::REDIS_VERSION;
+
+lazy_static::lazy_static! {
+    // identifies this server process, so clients can detect restarts
+    static ref RUN_ID: String = random_hex_id();
+    static ref STARTED_AT: Instant = Instant::now();
+}
+
+static TCP_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// Records the start time and listening port. Called once from `main`.
+pub fn init(port: u16) {
+    lazy_static::initialize(&RUN_ID);
+    lazy_static::initialize(&STARTED_AT);
+    TCP_PORT.store(port, Ordering::Relaxed);
+}
+
+pub fn run_id() -> &'static str {
+    &RUN_ID
+}
+
+/// The `INFO server` section.
+pub fn info() -> String {
+    [
+        format!("redis_version:{}", REDIS_VERSION),
+        format!("os:{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        format!("run_id:{}", run_id()),
+        format!("tcp_port:{}", TCP_PORT.load(Ordering::Relaxed)),
+        format!("uptime_in_seconds:{}", STARTED_AT.elapsed().as_secs()),
+    ]
+    .join("\r\n")
+}
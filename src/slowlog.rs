@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::resp::RedisValue;
+
+// same limits as Redis, so a huge command can't blow up the log
+const MAX_ARGS: usize = 32;
+const MAX_ARG_LEN: usize = 128;
+
+struct SlowlogEntry {
+    id: i64,
+    timestamp: i64,
+    duration_us: i64,
+    args: Vec<RedisValue>,
+    client_addr: String,
+}
+
+struct Slowlog {
+    entries: VecDeque<SlowlogEntry>,
+    next_id: i64,
+    // in microseconds; negative disables the log, 0 logs every command
+    log_slower_than: i64,
+    max_len: usize,
+}
+
+lazy_static::lazy_static! {
+    static ref SLOWLOG: Mutex<Slowlog> = Mutex::new(Slowlog {
+        entries: VecDeque::new(),
+        next_id: 0,
+        log_slower_than: 10000,
+        max_len: 128,
+    });
+}
+
+pub fn configure(log_slower_than: i64, max_len: usize) {
+    let mut slowlog = SLOWLOG.lock().unwrap();
+    slowlog.log_slower_than = log_slower_than;
+    slowlog.max_len = max_len;
+}
+
+/// Logs the command if it ran for longer than the configured threshold.
+pub fn record(duration: Duration, command: &RedisValue, client_addr: &str) {
+    let mut slowlog = SLOWLOG.lock().unwrap();
+    let duration_us = duration.as_micros() as i64;
+    if slowlog.log_slower_than < 0 || duration_us < slowlog.log_slower_than {
+        return;
+    }
+
+    let args = match command {
+        RedisValue::Array(items) => truncate_args(items),
+        other => vec![other.clone()],
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("clock is before the unix epoch")
+        .as_secs() as i64;
+    let id = slowlog.next_id;
+    slowlog.next_id += 1;
+    slowlog.entries.push_front(SlowlogEntry {
+        id,
+        timestamp,
+        duration_us,
+        args,
+        client_addr: client_addr.to_owned(),
+    });
+    let max_len = slowlog.max_len;
+    slowlog.entries.truncate(max_len);
+}
+
+fn truncate_args(items: &[RedisValue]) -> Vec<RedisValue> {
+    let kept = if items.len() > MAX_ARGS {
+        MAX_ARGS - 1
+    } else {
+        items.len()
+    };
+    let mut args: Vec<RedisValue> = items[..kept]
+        .iter()
+        .map(|item| match item {
+            RedisValue::BulkString(s) if s.len() > MAX_ARG_LEN => {
+                let mut end = MAX_ARG_LEN;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                RedisValue::BulkString(format!("{}... ({} more bytes)", &s[..end], s.len() - end))
+            }
+            other => other.clone(),
+        })
+        .collect();
+    if kept < items.len() {
+        args.push(RedisValue::BulkString(format!(
+            "... ({} more arguments)",
+            items.len() - kept
+        )));
+    }
+    args
+}
+
+/// The newest `count` entries, newest first. `None` returns the default 10,
+/// a negative count returns everything.
+pub fn get(count: Option<i64>) -> RedisValue {
+    let slowlog = SLOWLOG.lock().unwrap();
+    let count = match count {
+        Some(count) if count < 0 => slowlog.entries.len(),
+        Some(count) => count as usize,
+        None => 10,
+    };
+    RedisValue::Array(
+        slowlog
+            .entries
+            .iter()
+            .take(count)
+            .map(|entry| {
+                RedisValue::Array(vec![
+                    RedisValue::Integer(entry.id),
+                    RedisValue::Integer(entry.timestamp),
+                    RedisValue::Integer(entry.duration_us),
+                    RedisValue::Array(entry.args.clone()),
+                    RedisValue::BulkString(entry.client_addr.clone()),
+                    // client name, which this server has no way to set
+                    RedisValue::BulkString(String::new()),
+                ])
+            })
+            .collect(),
+    )
+}
+
+pub fn len() -> usize {
+    SLOWLOG.lock().unwrap().entries.len()
+}
+
+pub fn reset() {
+    SLOWLOG.lock().unwrap().entries.clear();
+}
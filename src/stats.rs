@@ -0,0 +1,35 @@
+//! The counters reported by `INFO stats`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TOTAL_CONNECTIONS_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static TOTAL_COMMANDS_PROCESSED: AtomicU64 = AtomicU64::new(0);
+
+pub fn connection_received() {
+    TOTAL_CONNECTIONS_RECEIVED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn command_processed() {
+    TOTAL_COMMANDS_PROCESSED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Zeroes every counter, for DEBUG RESET-STATS.
+pub fn reset() {
+    TOTAL_CONNECTIONS_RECEIVED.store(0, Ordering::Relaxed);
+    TOTAL_COMMANDS_PROCESSED.store(0, Ordering::Relaxed);
+}
+
+/// The `INFO stats` section.
+pub fn info() -> String {
+    [
+        format!(
+            "total_connections_received:{}",
+            TOTAL_CONNECTIONS_RECEIVED.load(Ordering::Relaxed)
+        ),
+        format!(
+            "total_commands_processed:{}",
+            TOTAL_COMMANDS_PROCESSED.load(Ordering::Relaxed)
+        ),
+    ]
+    .join("\r\n")
+}
@@ -0,0 +1,323 @@
+//! The stream type: an append-only log of field/value entries, ordered by
+//! strictly increasing `<ms>-<seq>` ids.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::Notify;
+
+use crate::resp::RedisValue;
+
+// entries per node in a Redis stream with the default stream-node-max-entries
+const NODE_MAX_ENTRIES: usize = 100;
+
+lazy_static::lazy_static! {
+    // clients blocked in XREAD, by the stream keys they wait on
+    static ref BLOCKED: Mutex<HashMap<RedisValue, Vec<Arc<Notify>>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses `<ms>-<seq>`, or a bare `<ms>` with the sequence number
+    /// defaulting to `missing_seq`.
+    pub fn parse(s: &str, missing_seq: u64) -> Result<StreamId> {
+        let parse = |part: &str| part.parse::<u64>().map_err(|_| invalid_id());
+        match s.split_once('-') {
+            Some((ms, seq)) => Ok(StreamId {
+                ms: parse(ms)?,
+                seq: parse(seq)?,
+            }),
+            None => Ok(StreamId {
+                ms: parse(s)?,
+                seq: missing_seq,
+            }),
+        }
+    }
+
+    /// The smallest id greater than this one.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    /// The largest id smaller than this one.
+    pub fn previous(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_sub(1)?,
+                seq: u64::MAX,
+            }),
+        }
+    }
+}
+
+/// The id argument of XADD.
+#[derive(Debug, Clone)]
+pub enum XAddId {
+    // `*`: both parts generated
+    Auto,
+    // `<ms>-*`: the sequence number generated
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl XAddId {
+    pub fn parse(s: &str) -> Result<XAddId> {
+        match s {
+            "*" => Ok(XAddId::Auto),
+            _ => match s.strip_suffix("-*") {
+                Some(ms) => Ok(XAddId::AutoSeq(ms.parse().map_err(|_| invalid_id())?)),
+                None => Ok(XAddId::Explicit(StreamId::parse(s, 0)?)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    // the highest id ever added, which new ids must exceed even once that
+    // entry is gone
+    pub last_id: StreamId,
+}
+
+impl Stream {
+    /// Appends an entry, returning the id it was stored under.
+    pub fn add(&mut self, id: XAddId, fields: Vec<(String, String)>) -> Result<StreamId> {
+        let last = self.last_id;
+        let id = match id {
+            XAddId::Auto => {
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .expect("clock is before the unix epoch")
+                    .as_millis() as u64;
+                // the clock may go backwards; ids must not
+                if now > last.ms {
+                    StreamId { ms: now, seq: 0 }
+                } else if last.seq == u64::MAX {
+                    StreamId {
+                        ms: last.ms + 1,
+                        seq: 0,
+                    }
+                } else {
+                    StreamId {
+                        ms: last.ms,
+                        seq: last.seq + 1,
+                    }
+                }
+            }
+            XAddId::AutoSeq(ms) if ms > last.ms => StreamId { ms, seq: 0 },
+            XAddId::AutoSeq(ms) if ms == last.ms && last.seq < u64::MAX => StreamId {
+                ms,
+                seq: last.seq + 1,
+            },
+            XAddId::AutoSeq(_) => return Err(id_too_small()),
+            XAddId::Explicit(StreamId { ms: 0, seq: 0 }) => {
+                return Err(anyhow::anyhow!(
+                    "The ID specified in XADD must be greater than 0-0"
+                ))
+            }
+            XAddId::Explicit(id) if id <= last => return Err(id_too_small()),
+            XAddId::Explicit(id) => id,
+        };
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        Ok(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Removes the entries with the given ids, returning how many existed.
+    /// The last id is kept, so deleted ids are never handed out again.
+    pub fn delete(&mut self, ids: &[StreamId]) -> usize {
+        ids.iter()
+            .filter(|id| self.entries.remove(id).is_some())
+            .count()
+    }
+
+    /// Removes the oldest entries as `strategy` asks, returning how many.
+    pub fn trim(&mut self, strategy: TrimStrategy) -> usize {
+        let before = self.len();
+        match strategy {
+            TrimStrategy::MaxLen(max_len) => {
+                while self.len() > max_len {
+                    self.entries.pop_first();
+                }
+            }
+            TrimStrategy::MinId(min_id) => self.entries = self.entries.split_off(&min_id),
+        }
+        before - self.len()
+    }
+
+    /// An estimate of the keys of the radix tree Redis would index these
+    /// entries with, as no such tree is kept here: one per node, with every
+    /// node holding `NODE_MAX_ENTRIES` consecutive entries. Redis leaves
+    /// nodes part empty after XDEL and XTRIM, so it may have more.
+    pub fn estimated_radix_tree_keys(&self) -> usize {
+        self.len().div_ceil(NODE_MAX_ENTRIES)
+    }
+
+    /// An estimate of the nodes of that radix tree: the root plus one per
+    /// key. Keys that share a prefix would split into a few more, so this is
+    /// a lower bound.
+    pub fn estimated_radix_tree_nodes(&self) -> usize {
+        1 + self.estimated_radix_tree_keys()
+    }
+
+    /// Entries with ids in `start..=end`, oldest first.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Vec<(String, String)>)> {
+        // BTreeMap::range panics on an inverted range
+        (start <= end)
+            .then(|| self.entries.range(start..=end))
+            .into_iter()
+            .flatten()
+    }
+}
+
+/// Which entries XTRIM keeps.
+#[derive(Debug, Clone, Copy)]
+pub enum TrimStrategy {
+    // the newest this many
+    MaxLen(usize),
+    // those with an id of at least this
+    MinId(StreamId),
+}
+
+/// Resolves the start of an XRANGE interval to an inclusive id: `-` is the
+/// smallest id, a bare `<ms>` starts at sequence 0, and a leading `(`
+/// excludes the id itself.
+pub fn range_start(s: &str) -> Result<StreamId> {
+    match s {
+        "-" => Ok(StreamId::MIN),
+        "+" => Ok(StreamId::MAX),
+        _ => match s.strip_prefix('(') {
+            Some(id) => {
+                let id = StreamId::parse(id, 0)?;
+                id.next()
+                    .ok_or_else(|| anyhow::anyhow!("invalid start ID for the interval"))
+            }
+            None => StreamId::parse(s, 0),
+        },
+    }
+}
+
+/// Like `range_start` for the end of the interval, where a bare `<ms>`
+/// takes in every sequence number.
+pub fn range_end(s: &str) -> Result<StreamId> {
+    match s {
+        "-" => Ok(StreamId::MIN),
+        "+" => Ok(StreamId::MAX),
+        _ => match s.strip_prefix('(') {
+            Some(id) => {
+                let id = StreamId::parse(id, u64::MAX)?;
+                id.previous()
+                    .ok_or_else(|| anyhow::anyhow!("invalid end ID for the interval"))
+            }
+            None => StreamId::parse(s, u64::MAX),
+        },
+    }
+}
+
+/// An entry as it is sent to clients: `[id, [field, value, ...]]`.
+pub fn entry_reply(id: &StreamId, fields: &[(String, String)]) -> RedisValue {
+    RedisValue::Array(vec![
+        RedisValue::BulkString(id.to_string()),
+        RedisValue::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| {
+                    [
+                        RedisValue::BulkString(field.clone()),
+                        RedisValue::BulkString(value.clone()),
+                    ]
+                })
+                .collect(),
+        ),
+    ])
+}
+
+fn invalid_id() -> anyhow::Error {
+    anyhow::anyhow!("Invalid stream ID specified as stream command argument")
+}
+
+fn id_too_small() -> anyhow::Error {
+    anyhow::anyhow!("The ID specified in XADD is equal or smaller than the target stream top item")
+}
+
+/// A client blocked on a set of streams. It is woken by `signal` for any of
+/// them, and stops waiting when dropped.
+pub struct BlockedReader {
+    keys: Vec<RedisValue>,
+    notify: Arc<Notify>,
+}
+
+impl BlockedReader {
+    /// Registers before the streams are first checked, so an entry added in
+    /// between still wakes the reader.
+    pub fn register(keys: Vec<RedisValue>) -> BlockedReader {
+        let notify = Arc::new(Notify::new());
+        let mut blocked = BLOCKED.lock().unwrap();
+        for key in &keys {
+            blocked.entry(key.clone()).or_default().push(notify.clone());
+        }
+        BlockedReader { keys, notify }
+    }
+
+    pub async fn wait(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for BlockedReader {
+    fn drop(&mut self) {
+        let mut blocked = BLOCKED.lock().unwrap();
+        for key in &self.keys {
+            if let Some(readers) = blocked.get_mut(key) {
+                readers.retain(|notify| !Arc::ptr_eq(notify, &self.notify));
+                if readers.is_empty() {
+                    blocked.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// Wakes every client blocked on the stream at `key`.
+pub fn signal(key: &RedisValue) {
+    if let Some(readers) = BLOCKED.lock().unwrap().get(key) {
+        for notify in readers {
+            notify.notify_one();
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::SystemTime;
+
+/// A random 40 character hex id, as used for run ids and replication ids.
+pub fn random_hex_id() -> String {
+    // every RandomState is seeded with fresh random keys
+    let state = RandomState::new();
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("clock is before the unix epoch")
+        .as_nanos();
+    let mut id = String::with_capacity(48);
+    for i in 0..3u64 {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(i);
+        hasher.write_u128(nanos);
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id.truncate(40);
+    id
+}
+
+/// Redis-style glob matching: `*` matches any run of bytes, `?` any single
+/// byte, `[abc]`, `[^abc]` and `[a-z]` a byte from a set, and `\` escapes
+/// the next character.
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+
+    let (mut p, mut s) = (0, 0);
+    // the most recent `*` and where in the string it started matching; on a
+    // mismatch it is made to swallow one more byte and matching resumes
+    // after it. Backtracking to the latest star only is enough, so this
+    // never goes exponential on patterns like `a*a*a*a*b`.
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if p < pattern.len() {
+            let c = string[s];
+            let advance = match pattern[p] {
+                b'*' => {
+                    star = Some((p, s));
+                    p += 1;
+                    continue;
+                }
+                b'?' => Some(p + 1),
+                b'[' => {
+                    let (matched, next) = match_class(pattern, p, c, nocase);
+                    matched.then_some(next)
+                }
+                // a trailing backslash is matched literally
+                b'\\' if p + 1 < pattern.len() => eq(pattern[p + 1], c).then_some(p + 2),
+                literal => eq(literal, c).then_some(p + 1),
+            };
+            if let Some(next) = advance {
+                p = next;
+                s += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((star_p, star_s)) => {
+                star = Some((star_p, star_s + 1));
+                p = star_p + 1;
+                s = star_s + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Matches `c` against the `[...]` class opening at `pattern[start]`.
+/// Returns whether it matched and the index just past the class; an
+/// unterminated class runs to the end of the pattern.
+fn match_class(pattern: &[u8], start: usize, c: u8, nocase: bool) -> (bool, usize) {
+    let fold = |b: u8| if nocase { b.to_ascii_lowercase() } else { b };
+    let c = fold(c);
+    let mut i = start + 1;
+    let negated = pattern.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < pattern.len() {
+        match pattern[i] {
+            b']' => {
+                i += 1;
+                break;
+            }
+            b'\\' if i + 1 < pattern.len() => {
+                matched |= fold(pattern[i + 1]) == c;
+                i += 2;
+            }
+            from if i + 2 < pattern.len() && pattern[i + 1] == b'-' => {
+                let (from, to) = (fold(from), fold(pattern[i + 2]));
+                let (low, high) = if from <= to { (from, to) } else { (to, from) };
+                matched |= (low..=high).contains(&c);
+                i += 3;
+            }
+            literal => {
+                matched |= fold(literal) == c;
+                i += 1;
+            }
+        }
+    }
+    (matched != negated, i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        glob_match(pattern.as_bytes(), string.as_bytes(), false)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("h*", "h"));
+        assert!(!matches("h*x", "hello"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        // reversed ranges are accepted, as in Redis
+        assert!(matches("h[b-a]llo", "hallo"));
+        // an unterminated class swallows the rest of the pattern
+        assert!(matches("h[el", "he"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+        assert!(matches("\\[a\\]", "[a]"));
+        assert!(matches("[\\]]", "]"));
+        assert!(matches("a[\\-]b", "a-b"));
+        // a trailing backslash matches itself
+        assert!(matches("a\\", "a\\"));
+    }
+
+    #[test]
+    fn nocase() {
+        assert!(glob_match(b"H[A-C]LLO", b"hbllo", true));
+        assert!(!glob_match(b"H[A-C]LLO", b"hbllo", false));
+    }
+
+    #[test]
+    fn adversarial_patterns_finish_quickly() {
+        let string = "a".repeat(10_000);
+        assert!(!matches("a*a*a*a*a*a*a*a*a*a*b", &string));
+        assert!(matches("a*a*a*a*a*a*a*a*a*a*", &string));
+        assert!(!matches(&"*a".repeat(1000), &"a".repeat(999)));
+        assert!(matches("*?*?*?*?*", "abcd"));
+        assert!(!matches("*?*?*?*?*?*", "abcd"));
+        assert!(matches("***********x", "yyyyyyyyyyyyyyyyyyyyyyyyx"));
+    }
+}